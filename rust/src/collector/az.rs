@@ -10,24 +10,50 @@ Any modifications or derivative works of this code must retain this
 copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
+use std::sync::atomic::{AtomicU64, Ordering};
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use anyhow::Result;
 
-use crate::rl::env::Env;
+use crate::rl::env::{Env, Observation};
+use crate::rl::env::strict::debug_validate_action;
+use crate::rl::rng::{derive_seed, episode_seeds, seeded_rng};
 use crate::nn::policy::{Policy, sample};
 use crate::collector::collector::{CollectedData, Collector, merge};
+use crate::collector::difficulty::DifficultyDistribution;
 use crate::rl::search::predict_probs_mcts;
 
-#[derive(Clone)]
+// `C` is the exploration constant of the search, named as in the configs and the Python API
+#[allow(non_snake_case)]
 pub struct AZCollector {
     pub num_episodes: usize,
     pub num_mcts_searches: usize,
     pub C: f32,
     pub max_expand_depth: usize,
     pub num_cores: usize,
+    pub seed: u64,
+    // Distribution the difficulty of each episode is sampled from
+    pub difficulty_distribution: DifficultyDistribution,
+    // Number of `collect` calls so far, so that consecutive calls collect different episodes
+    round: AtomicU64,
 }
 
+impl Clone for AZCollector {
+    fn clone(&self) -> Self {
+        AZCollector {
+            num_episodes: self.num_episodes,
+            num_mcts_searches: self.num_mcts_searches,
+            C: self.C,
+            max_expand_depth: self.max_expand_depth,
+            num_cores: self.num_cores,
+            seed: self.seed,
+            difficulty_distribution: self.difficulty_distribution,
+            round: AtomicU64::new(self.round.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[allow(non_snake_case)]
 impl AZCollector {
     pub fn new(
         num_episodes: usize,
@@ -35,6 +61,7 @@ impl AZCollector {
         C: f32,
         max_expand_depth: usize,
         num_cores: usize,
+        seed: u64,
     ) -> Self {
         AZCollector {
             num_episodes,
@@ -42,56 +69,74 @@ impl AZCollector {
             C,
             max_expand_depth,
             num_cores,
+            seed,
+            difficulty_distribution: DifficultyDistribution::Fixed,
+            round: AtomicU64::new(0),
         }
     }
 }
 
 impl AZCollector {
     /// Runs one episode, returns its `CollectedData`
+    #[allow(clippy::borrowed_box)]
     fn single_collect(
         &self,
         env: &Box<dyn Env>,
         policy: &Policy,
-    ) -> CollectedData {
+        seed: u64,
+        episode: usize,
+    ) -> Result<CollectedData> {
+        let (env_seed, search_seed) = episode_seeds(seed, episode);
+        let mut rng = seeded_rng(search_seed);
+
         let mut env = env.clone();
-        env.reset();
+        env.try_set_seed(env_seed)?;
+        let difficulty = self.difficulty_distribution.sample(env.get_difficulty(), &mut seeded_rng(derive_seed(env_seed, 0)));
+        env.set_difficulty(difficulty);
+        env.try_reset()?;
 
         // Init data vecs
-        let mut obs: Vec<Vec<usize>> = vec![];
+        let mut obs: Vec<Observation> = vec![];
         let mut probs: Vec<Vec<f32>> = vec![];
         let mut vals: Vec<f32> = vec![];
-        let mut total_vals: Vec<f32> = vec![];
-
-        let mut total_val = 0.0;
+        let mut players: Vec<usize> = vec![];
+        let mut successes: Vec<f32> = vec![];
 
         // Loop until a final state
         loop {
             // Calculate MCTS probs for current state
-            let mcts_probs = predict_probs_mcts(env.clone(), policy, self.num_mcts_searches, self.C, self.max_expand_depth);
+            let mcts_probs = predict_probs_mcts(&mut env, policy, self.num_mcts_searches, self.C, self.max_expand_depth, &mut rng)?;
 
             // Select next action and get current value
-            let action = sample(&mcts_probs);
-            let val = env.reward();
-            total_vals.push(total_val);
-
-            total_val += val;
+            let action = sample(&mcts_probs, &mut rng);
+            let val = env.try_reward()?;
 
             // Store data
-            obs.push(env.observe());
+            obs.push(env.try_observation()?);
             probs.push(mcts_probs);
             vals.push(val);
+            players.push(env.try_current_player()?);
+            successes.push(env.try_is_success()? as usize as f32);
 
             // Break if we are in a final state
-            if env.is_final() {
+            if env.try_is_final()? {
                 break;
             }
 
             // Move to next state
-            env.step(action);
+            debug_validate_action(env.as_ref(), action)?;
+            env.try_step(action)?;
         }
 
-        // Post process rewards
-        let remaining_vals: Vec<f32> = total_vals.iter().map(|&v| total_val - v).collect();
+        // Post process rewards: sum of the remaining rewards from the point of view of the player to move,
+        // where rewards of the other players count negatively (two-player zero-sum games)
+        let mut player_totals = vec![0.0f32; env.try_num_players()?];
+        let mut remaining_vals = vec![0.0f32; vals.len()];
+        for t in (0..vals.len()).rev() {
+            player_totals[players[t]] += vals[t];
+            let total: f32 = player_totals.iter().sum();
+            remaining_vals[t] = 2.0 * player_totals[players[t]] - total;
+        }
 
         let mut data = CollectedData::new(
             obs,
@@ -102,27 +147,33 @@ impl AZCollector {
         );
         data.additional_data
             .insert("remaining_values".into(), remaining_vals);
+        data.additional_data
+            .insert("difficulties".into(), vec![difficulty as f32; successes.len()]);
+        data.additional_data
+            .insert("successes".into(), successes);
 
-        data
+        Ok(data)
     }
 }
 
 impl Collector for AZCollector {
     fn collect(&self, env: &Box<dyn Env>, policy: &Policy) -> Result<CollectedData> {
+        // Episodes are seeded from (seed, round, episode index), independently of num_cores
+        let seed = derive_seed(self.seed, self.round.fetch_add(1, Ordering::Relaxed));
         if self.num_cores == 1 {
             Ok(merge(
-                (0..self.num_episodes).into_iter()
-                    .map(|_| self.single_collect(env, policy))
-                    .collect()
+                (0..self.num_episodes)
+                    .map(|i| self.single_collect(env, policy, seed, i))
+                    .collect::<Result<_>>()?
             )?)
         } else {
             let pool: rayon::ThreadPool = ThreadPoolBuilder::new().num_threads(self.num_cores).build()?;
             // Use the thread pool to run the generation in parallel
             Ok(merge(pool.install(|| {
                 (0..self.num_episodes).into_par_iter()  // Create a parallel iterator over the range 0..num_episodes
-                    .map(|_| self.single_collect(env, policy))
-                    .collect()
-            }))?)
+                    .map(|i| self.single_collect(env, policy, seed, i))
+                    .collect::<Result<_>>()
+            })?)?)
         }
     }
 }
@@ -134,25 +185,25 @@ mod tests {
     use crate::nn::modules::Sequential;
     use crate::nn::policy::Policy;
     use crate::rl::env::Env;
+    use crate::rl::env::testing::DummyEnv;
 
+    // Two moves, one per player; the player to move at the end has lost
     #[derive(Clone)]
-    struct DummyEnv { step: usize }
-
-    impl DummyEnv {
-        fn new() -> Self { Self { step: 0 } }
-    }
+    struct TwoPlayerEnv(DummyEnv);
 
-    impl Env for DummyEnv {
+    impl Env for TwoPlayerEnv {
         fn as_any(&self) -> &dyn std::any::Any { self }
         fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
         fn num_actions(&self) -> usize { 1 }
         fn obs_shape(&self) -> Vec<usize> { vec![1] }
-        fn set_state(&mut self, state: Vec<i64>) { self.step = state[0] as usize; }
-        fn reset(&mut self) { self.step = 0; }
-        fn step(&mut self, _action: usize) { self.step += 1; }
-        fn masks(&self) -> Vec<bool> { vec![true] }
-        fn is_final(&self) -> bool { self.step >= 1 }
-        fn reward(&self) -> f32 { 1.0 }
+        fn num_players(&self) -> usize { 2 }
+        fn current_player(&self) -> usize { self.0.step % 2 }
+        fn set_state(&mut self, state: Vec<i64>) { self.0.set_state(state) }
+        fn get_state(&self) -> Vec<i64> { self.0.get_state() }
+        fn reset(&mut self) { self.0.reset() }
+        fn step(&mut self, action: usize) { self.0.step(action) }
+        fn is_final(&self) -> bool { self.0.step >= 2 }
+        fn reward(&self) -> f32 { if self.is_final() { -1.0 } else { 0.0 } }
         fn observe(&self) -> Vec<usize> { vec![0] }
     }
 
@@ -175,11 +226,21 @@ mod tests {
     fn test_azcollector_collect() {
         let env: Box<dyn Env> = Box::new(DummyEnv::new());
         let policy = dummy_policy();
-        let collector = AZCollector::new(1, 1, 1.0, 1, 1);
+        let collector = AZCollector::new(1, 1, 1.0, 1, 1, 0);
 
         let data = collector.collect(&env, &policy).unwrap();
         assert_eq!(data.obs.len(), 2);
         assert!(data.additional_data.contains_key("remaining_values"));
+        assert!(data.additional_data.contains_key("successes"));
     }
-}
 
+    #[test]
+    fn test_azcollector_two_player_values() {
+        let env: Box<dyn Env> = Box::new(TwoPlayerEnv(DummyEnv::new()));
+        let policy = dummy_policy();
+        let collector = AZCollector::new(1, 4, 1.0, 2, 1, 0);
+
+        let data = collector.collect(&env, &policy).unwrap();
+        assert_eq!(data.additional_data["remaining_values"], vec![-1.0, 1.0, -1.0]);
+    }
+}
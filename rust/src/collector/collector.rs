@@ -15,13 +15,13 @@ use std::collections::HashMap;
 use anyhow::{anyhow, Result};
 
 use crate::nn::policy::Policy;
-use crate::rl::env::Env;
+use crate::rl::env::{Env, Observation};
 
 
 /// Container for collected rollout data using plain Rust vectors.
 pub struct CollectedData {
-    /// Observations at each timestep: Vec of feature Vecs
-    pub obs: Vec<Vec<usize>>,
+    /// Observations at each timestep (sparse, weighted or dense)
+    pub obs: Vec<Observation>,
     /// Logits (action probabilities) at each timestep
     pub logits: Vec<Vec<f32>>,
     /// Value estimates at each timestep
@@ -46,7 +46,7 @@ pub fn merge(mut chunks: Vec<CollectedData>) -> Result<CollectedData> {
 impl CollectedData {
     /// Construct a new CollectedData from raw rollout vectors.
     pub fn new(
-        obs: Vec<Vec<usize>>,
+        obs: Vec<Observation>,
         logits: Vec<Vec<f32>>,
         values: Vec<f32>,
         rewards: Vec<f32>,
@@ -86,6 +86,8 @@ impl CollectedData {
 /// A generic trait for collecting data.
 pub trait Collector: Send + Sync {
     /// Runs the collection process and returns accumulated data.
+    // The env is boxed so that it can be cloned into the worker threads with `clone_box`
+    #[allow(clippy::borrowed_box)]
     fn collect(&self, env: &Box<dyn Env>, policy: &Policy) -> Result<CollectedData>;
 }
 
@@ -96,7 +98,7 @@ mod tests {
     #[test]
     fn test_merge_collected_data() {
         let d1 = CollectedData::new(
-            vec![vec![0]],
+            vec![Observation::Sparse(vec![0])],
             vec![vec![0.1]],
             vec![0.2],
             vec![0.3],
@@ -104,7 +106,7 @@ mod tests {
         );
 
         let d2 = CollectedData::new(
-            vec![vec![1]],
+            vec![Observation::Dense(vec![0.0, 1.0])],
             vec![vec![0.4]],
             vec![0.5],
             vec![0.6],
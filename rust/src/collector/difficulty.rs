@@ -0,0 +1,67 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use rand::Rng;
+use serde::Deserialize;
+
+// Distribution of the difficulty of collected episodes, relative to the current difficulty d of the env.
+// From JSON: {"type": "fixed"}, {"type": "uniform"} or {"type": "replay", "replay_prob": 0.2}
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DifficultyDistribution {
+    // Always d
+    #[default]
+    Fixed,
+    // Uniform over 1..=d
+    Uniform,
+    // d, except with probability replay_prob an earlier level, uniform over 1..d
+    Replay { replay_prob: f32 },
+}
+
+impl DifficultyDistribution {
+    // Samples the difficulty of an episode given the current difficulty
+    pub fn sample(&self, difficulty: usize, rng: &mut impl Rng) -> usize {
+        match *self {
+            DifficultyDistribution::Fixed => difficulty,
+            DifficultyDistribution::Uniform if difficulty > 1 => rng.gen_range(1..=difficulty),
+            DifficultyDistribution::Replay { replay_prob } if difficulty > 1 && rng.gen::<f32>() < replay_prob => {
+                rng.gen_range(1..difficulty)
+            }
+            _ => difficulty,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rl::rng::seeded_rng;
+
+    #[test]
+    fn test_difficulty_distributions() {
+        let mut rng = seeded_rng(0);
+        let sample = |dist: DifficultyDistribution, rng: &mut _| (0..200).map(|_| dist.sample(5, rng)).collect::<Vec<usize>>();
+
+        assert!(sample(DifficultyDistribution::Fixed, &mut rng).iter().all(|&d| d == 5));
+        let uniform = sample(DifficultyDistribution::Uniform, &mut rng);
+        assert!((1..=5).all(|d| uniform.contains(&d)) && !uniform.contains(&0));
+        let replay = sample(DifficultyDistribution::Replay { replay_prob: 0.25 }, &mut rng);
+        let num_replayed = replay.iter().filter(|&&d| d < 5).count();
+        assert!(num_replayed > 20 && num_replayed < 80);
+        assert!(replay.iter().all(|&d| (1..=5).contains(&d)));
+
+        let replay: DifficultyDistribution = serde_json::from_str(r#"{"type": "replay", "replay_prob": 0.2}"#).unwrap();
+        assert_eq!(replay, DifficultyDistribution::Replay { replay_prob: 0.2 });
+        assert!(serde_json::from_str::<DifficultyDistribution>(r#"{"type": "normal"}"#).is_err());
+    }
+}
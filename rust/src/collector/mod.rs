@@ -11,6 +11,9 @@ copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
 
+// The Collector trait, shared by the collectors of the sibling modules
+#[allow(clippy::module_inception)]
 pub mod collector;
 pub mod az;
-pub mod ppo;
\ No newline at end of file
+pub mod ppo;
+pub mod difficulty;
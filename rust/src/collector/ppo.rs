@@ -10,20 +10,46 @@ Any modifications or derivative works of this code must retain this
 copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
+use std::sync::atomic::{AtomicU64, Ordering};
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use anyhow::Result;
+use rand::Rng;
 
 use crate::collector::collector::{Collector, CollectedData, merge};
+use crate::collector::difficulty::DifficultyDistribution;
 use crate::nn::policy::{Policy, sample_from_logits};
-use crate::rl::env::Env;
+use crate::rl::env::{Env, EnvResult, Observation};
+use crate::rl::env::strict::debug_validate_action;
+use crate::rl::rng::{derive_seed, episode_seeds, seeded_rng};
+
+// Data of a single step: (obs, logits, action, value, reward)
+type StepData = (Observation, Vec<f32>, usize, f32, f32);
 
-#[derive(Clone)]
 pub struct PPOCollector {
     pub num_episodes: usize,
     pub gamma: f32,
     pub lambda: f32,
     pub num_cores: usize,
+    pub seed: u64,
+    // Distribution the difficulty of each episode is sampled from
+    pub difficulty_distribution: DifficultyDistribution,
+    // Number of `collect` calls so far, so that consecutive calls collect different episodes
+    round: AtomicU64,
+}
+
+impl Clone for PPOCollector {
+    fn clone(&self) -> Self {
+        PPOCollector {
+            num_episodes: self.num_episodes,
+            gamma: self.gamma,
+            lambda: self.lambda,
+            num_cores: self.num_cores,
+            seed: self.seed,
+            difficulty_distribution: self.difficulty_distribution,
+            round: AtomicU64::new(self.round.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl PPOCollector {
@@ -32,8 +58,9 @@ impl PPOCollector {
         gamma: f32,
         lambda: f32,
         num_cores: usize,
+        seed: u64,
     ) -> Self {
-        PPOCollector { num_episodes, gamma, lambda, num_cores }
+        PPOCollector { num_episodes, gamma, lambda, num_cores, seed, difficulty_distribution: DifficultyDistribution::Fixed, round: AtomicU64::new(0) }
     }
 }
 
@@ -42,47 +69,72 @@ impl PPOCollector {
         &self,
         env: &dyn Env,
         policy: &Policy,
-    ) -> (Vec<usize>, Vec<f32>, usize, f32, f32) {
-        let obs = env.observe();      // Vec<f32> or whatever your Env returns
-        let masks   = env.masks();
-        let reward  = env.reward();
-        let (logits, value) = policy.forward(obs.clone(), masks);  
-        let action = sample_from_logits(&logits);
-        (obs, logits, action, value, reward)
+        rng: &mut impl Rng,
+    ) -> EnvResult<StepData> {
+        let obs = env.try_observation()?;
+        let reward  = env.try_reward()?;
+        // Factored policies sample the action components one after the other
+        if policy.is_factored() {
+            let (logits, value, action) = policy.forward_env(env, rng)?;
+            return Ok((obs, logits, action, value, reward));
+        }
+        let masks   = env.try_masks()?;
+        let (logits, value) = policy.forward(obs.clone(), masks, rng);
+        let action = sample_from_logits(&logits, rng);
+        Ok((obs, logits, action, value, reward))
     }
 
+    #[allow(clippy::borrowed_box)]
     fn single_collect(
         & self,
         env: &Box<dyn Env>,
         policy: &Policy,
-    ) -> CollectedData {
+        seed: u64,
+        episode: usize,
+    ) -> Result<CollectedData> {
+        let (env_seed, policy_seed) = episode_seeds(seed, episode);
+        let mut rng = seeded_rng(policy_seed);
+
         let mut env = env.clone();
-        env.reset(); // We do not care about the original env in the collect
+        env.try_set_seed(env_seed)?;
+        let difficulty = self.difficulty_distribution.sample(env.get_difficulty(), &mut seeded_rng(derive_seed(env_seed, 0)));
+        env.set_difficulty(difficulty);
+        env.try_reset()?; // We do not care about the original env in the collect
 
         let mut obss = Vec::new();
         let mut log_probs  = Vec::new();
         let mut vals  = Vec::new();
         let mut rews = Vec::new();
         let mut acts = Vec::new();
+        let mut succs = Vec::new();
 
         loop {
-            let (obs, log_prob, act, val, rew) = self.get_step_data(&*env, policy);
+            let (obs, log_prob, act, val, rew) = self.get_step_data(&*env, policy, &mut rng)?;
             obss.push(obs);
             log_probs.push(log_prob);
             vals.push(val);
             rews.push(rew);
             acts.push(act);
+            succs.push(env.try_is_success()? as usize as f32);
 
-            if env.is_final() { break; }
-            env.step(act);
+            if env.try_is_final()? { break; }
+            debug_validate_action(env.as_ref(), act)?;
+            env.try_step(act)?;
         }
+        let truncated = env.try_is_truncated()?;
 
         // compute GAE advs/rets
         let n = rews.len();
         let mut advs = vec![0.0; n];
         let mut rets = vec![0.0; n];
-        advs[n-1] = rews[n-1] - vals[n-1];
-        rets[n-1] = rews[n-1];
+        if truncated {
+            // The episode was cut by a time limit, so bootstrap from the value estimate of the last state
+            advs[n-1] = 0.0;
+            rets[n-1] = vals[n-1];
+        } else {
+            advs[n-1] = rews[n-1] - vals[n-1];
+            rets[n-1] = rews[n-1];
+        }
         for t in (0..n-1).rev() {
             rets[t] = rews[t]
                     + self.gamma * (vals[t+1] + self.lambda * advs[t+1]);
@@ -98,26 +150,30 @@ impl PPOCollector {
         );
         data.additional_data.insert("advs".into(), advs);
         data.additional_data.insert("rets".into(), rets);
-        data
+        data.additional_data.insert("successes".into(), succs);
+        data.additional_data.insert("difficulties".into(), vec![difficulty as f32; n]);
+        Ok(data)
     }
 }
 
 impl Collector for PPOCollector {
     fn collect(&self, env: &Box<dyn Env>, policy: &Policy) -> Result<CollectedData> {
+        // Episodes are seeded from (seed, round, episode index), independently of num_cores
+        let seed = derive_seed(self.seed, self.round.fetch_add(1, Ordering::Relaxed));
         if self.num_cores == 1 {
             Ok(merge(
-                (0..self.num_episodes).into_iter()  // Create a parallel iterator over the range 0..num_episodes
-                    .map(|_| self.single_collect(env, policy)) // For each item in the range run a collection
-                    .collect())?
+                (0..self.num_episodes)  // Create an iterator over the range 0..num_episodes
+                    .map(|i| self.single_collect(env, policy, seed, i)) // For each item in the range run a collection
+                    .collect::<Result<_>>()?)?
             )
         } else {
             let pool: rayon::ThreadPool = ThreadPoolBuilder::new().num_threads(self.num_cores).build()?;
             // Use the thread pool to run the generation in parallel
             Ok(merge(pool.install(|| {
                 (0..self.num_episodes).into_par_iter()  // Create a parallel iterator over the range 0..num_episodes
-                    .map(|_| self.single_collect(env, policy)) // For each item in the range run a collection
-                    .collect()
-            }))?)
+                    .map(|i| self.single_collect(env, policy, seed, i)) // For each item in the range run a collection
+                    .collect::<Result<_>>()
+            })?)?)
         }
     }
 }
@@ -129,27 +185,8 @@ mod tests {
     use crate::nn::modules::Sequential;
     use crate::nn::policy::Policy;
     use crate::rl::env::Env;
-
-    #[derive(Clone)]
-    struct DummyEnv { step: usize }
-
-    impl DummyEnv {
-        fn new() -> Self { Self { step: 0 } }
-    }
-
-    impl Env for DummyEnv {
-        fn as_any(&self) -> &dyn std::any::Any { self }
-        fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
-        fn num_actions(&self) -> usize { 1 }
-        fn obs_shape(&self) -> Vec<usize> { vec![1] }
-        fn set_state(&mut self, state: Vec<i64>) { self.step = state[0] as usize; }
-        fn reset(&mut self) { self.step = 0; }
-        fn step(&mut self, _action: usize) { self.step += 1; }
-        fn masks(&self) -> Vec<bool> { vec![true] }
-        fn is_final(&self) -> bool { self.step >= 1 }
-        fn reward(&self) -> f32 { 1.0 }
-        fn observe(&self) -> Vec<usize> { vec![0] }
-    }
+    use crate::rl::env::testing::{DummyEnv, TestEnv};
+    use crate::envs::puzzle::Puzzle;
 
     fn dummy_policy() -> Policy {
         let emb = EmbeddingBag::new(vec![vec![1.0]], vec![0.0], false, vec![1], 0);
@@ -166,15 +203,102 @@ mod tests {
         )
     }
 
+    fn puzzle_policy() -> Policy {
+        let emb = EmbeddingBag::new((0..81).map(|i| vec![(i % 7) as f32 * 0.1]).collect(), vec![0.0], false, vec![81], 0);
+        let seq_a = Sequential::new(vec![Box::new(Linear::new(vec![1.0, -1.0, 0.5, 0.2], vec![0.0; 4], false))]);
+        let seq_v = Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))]);
+        Policy::new(
+            Box::new(emb),
+            Box::new(Sequential::new(vec![])),
+            Box::new(seq_a),
+            Box::new(seq_v),
+            vec![],
+            vec![],
+        )
+    }
+
     #[test]
     fn test_ppocollector_collect() {
         let env: Box<dyn Env> = Box::new(DummyEnv::new());
         let policy = dummy_policy();
-        let collector = PPOCollector::new(1, 0.9, 0.95, 1);
+        let collector = PPOCollector::new(1, 0.9, 0.95, 1, 0);
 
         let data = collector.collect(&env, &policy).unwrap();
         assert_eq!(data.obs.len(), 2);
         assert!(data.additional_data.contains_key("rets"));
+        assert_eq!(data.additional_data["successes"], vec![1.0, 1.0]);
     }
-}
 
+    #[test]
+    fn test_ppocollector_bootstraps_truncated_episodes() {
+        let mut env = TestEnv::new(Box::new(DummyEnv::new()));
+        env.truncated = true;
+        let env: Box<dyn Env> = Box::new(env);
+        let policy = dummy_policy();
+        let collector = PPOCollector::new(1, 0.9, 0.95, 1, 0);
+
+        let data = collector.collect(&env, &policy).unwrap();
+        let rets = &data.additional_data["rets"];
+        let advs = &data.additional_data["advs"];
+        assert_eq!(rets[1], data.values[1]);
+        assert_eq!(advs[1], 0.0);
+        assert_eq!(rets[0], data.rewards[0] + 0.9 * data.values[1]);
+    }
+
+    #[test]
+    fn test_ppocollector_propagates_env_errors() {
+        let mut env = TestEnv::new(Box::new(DummyEnv::new()));
+        env.step_error = Some("boom".into());
+        let env: Box<dyn Env> = Box::new(env);
+        let policy = dummy_policy();
+        let collector = PPOCollector::new(2, 0.9, 0.95, 2, 0);
+
+        let err = collector.collect(&env, &policy).err().expect("collect should fail");
+        assert_eq!(err.to_string(), "Env method `step` failed: boom");
+    }
+
+    #[test]
+    fn test_ppocollector_seeded_collect_independent_of_cores() {
+        let env: Box<dyn Env> = Box::new(Puzzle::new(3, 3, 8, 2, 16));
+        let policy = puzzle_policy();
+
+        let data_1 = PPOCollector::new(8, 0.9, 0.95, 1, 5).collect(&env, &policy).unwrap();
+        let data_4 = PPOCollector::new(8, 0.9, 0.95, 4, 5).collect(&env, &policy).unwrap();
+        assert_eq!(data_1.obs, data_4.obs);
+        assert_eq!(data_1.actions, data_4.actions);
+        assert_eq!(data_1.rewards, data_4.rewards);
+    }
+
+    #[test]
+    fn test_ppocollector_samples_difficulties() {
+        let env: Box<dyn Env> = Box::new(Puzzle::new(3, 3, 6, 2, 16));
+        let policy = puzzle_policy();
+
+        let data = PPOCollector::new(4, 0.9, 0.95, 1, 3).collect(&env, &policy).unwrap();
+        assert!(data.additional_data["difficulties"].iter().all(|&d| d == 6.0));
+
+        let mut collector = PPOCollector::new(20, 0.9, 0.95, 1, 3);
+        collector.difficulty_distribution = DifficultyDistribution::Uniform;
+        let data = collector.collect(&env, &policy).unwrap();
+        let difficulties = &data.additional_data["difficulties"];
+        assert_eq!(difficulties.len(), data.obs.len());
+        assert!(difficulties.iter().all(|&d| (1.0..=6.0).contains(&d)));
+        assert!(difficulties.iter().any(|&d| d < 6.0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_ppocollector_rejects_masked_actions() {
+        // NaN logits make the sampling pick the masked action
+        let emb = EmbeddingBag::new(vec![vec![1.0]], vec![0.0], false, vec![1], 0);
+        let seq_a = Sequential::new(vec![Box::new(Linear::new(vec![f32::NAN, f32::NAN], vec![0.0; 2], false))]);
+        let seq_v = Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))]);
+        let policy = Policy::new(Box::new(emb), Box::new(Sequential::new(vec![])), Box::new(seq_a), Box::new(seq_v), vec![], vec![]);
+        let mut env = TestEnv::new(Box::new(DummyEnv::new()));
+        env.masks = Some(vec![false, true]);
+        let env: Box<dyn Env> = Box::new(env);
+
+        let err = PPOCollector::new(1, 0.9, 0.95, 1, 0).collect(&env, &policy).err().expect("collect should fail");
+        assert_eq!(err.to_string(), "Action 0 is not allowed, the action masks are [false, true]");
+    }
+}
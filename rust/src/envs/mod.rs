@@ -11,4 +11,6 @@ copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
 
-pub mod puzzle;
\ No newline at end of file
+pub mod permutation;
+pub mod puzzle;
+pub mod registry;
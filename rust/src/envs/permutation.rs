@@ -0,0 +1,399 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::rl::env::{Env, EnvError, EnvResult};
+use crate::rl::rng::seeded_rng;
+
+// Symmetry of a generator set: relabeling the positions (and values) i as positions[i] maps the action a
+// to the action actions[a]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Symmetry {
+    pub positions: Vec<usize>,
+    pub actions: Vec<usize>,
+}
+
+// The moves of a permutation puzzle, e.g. from a JSON file:
+// {"generators": [[1, 0, 2], [0, 2, 1]], "names": ["swap01", "swap12"], "symmetries": []}
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorSet {
+    // Applying generator g moves the value at position g[i] to position i
+    pub generators: Vec<Vec<usize>>,
+    // Optional action names, one per generator
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub symmetries: Vec<Symmetry>,
+}
+
+impl GeneratorSet {
+    pub fn new(generators: Vec<Vec<usize>>) -> Self {
+        GeneratorSet { generators, names: vec![], symmetries: vec![] }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&data).with_context(|| format!("Invalid generator file {}", path.display()))
+    }
+
+    // Checks that the generators are permutations of the same size, and that the symmetries commute with them
+    pub fn validate(&self) -> Result<()> {
+        let Some(size) = self.generators.first().map(|g| g.len()) else {
+            bail!("at least one generator is needed");
+        };
+        for generator in &self.generators {
+            if !is_permutation(generator, size) {
+                bail!("generator {:?} is not a permutation of 0..{}", generator, size);
+            }
+        }
+        if !self.names.is_empty() && self.names.len() != self.generators.len() {
+            bail!("{} names for {} generators", self.names.len(), self.generators.len());
+        }
+        for symmetry in &self.symmetries {
+            let (p, actions) = (&symmetry.positions, &symmetry.actions);
+            if !is_permutation(p, size) || !is_permutation(actions, self.generators.len()) {
+                bail!("symmetry {:?} must permute 0..{} and the {} actions", symmetry, size, self.generators.len());
+            }
+            // The relabeled env takes actions[a] when the env takes a
+            for (a, generator) in self.generators.iter().enumerate() {
+                let image = &self.generators[actions[a]];
+                if (0..size).any(|i| image[p[i]] != p[generator[i]]) {
+                    bail!("symmetry {:?} does not map generator {} to generator {}", symmetry, a, actions[a]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Config of a permutation puzzle, with the generators given inline or in a generator file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermutationPuzzleConfig {
+    #[serde(default)]
+    pub generators: Option<Vec<Vec<usize>>>,
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub symmetries: Vec<Symmetry>,
+    #[serde(default)]
+    pub generators_file: Option<String>,
+    pub difficulty: usize,
+    pub depth_slope: usize,
+    pub max_depth: usize,
+}
+
+impl PermutationPuzzleConfig {
+    pub fn generator_set(self) -> Result<GeneratorSet> {
+        match (self.generators, self.generators_file) {
+            (Some(generators), None) => Ok(GeneratorSet { generators, names: self.names, symmetries: self.symmetries }),
+            (None, Some(path)) if self.names.is_empty() && self.symmetries.is_empty() => GeneratorSet::load(path),
+            (None, Some(_)) => bail!("names and symmetries go in the generator file"),
+            _ => bail!("exactly one of generators and generators_file must be given"),
+        }
+    }
+}
+
+// Puzzle over the permutations of 0..size, where each action applies one of the generators and the goal
+// is the identity. Covers Rubik-like puzzles, TopSpin, pancake sorting, ...
+#[derive(Clone)]
+pub struct PermutationPuzzle {
+    pub state: Vec<usize>,
+    pub depth: usize,
+
+    pub generators: GeneratorSet,
+    pub difficulty: usize,
+    pub depth_slope: usize,
+    pub max_depth: usize,
+
+    rng: StdRng,
+    // Whether each step since the last reset or set_state decremented the depth, so that `undo` restores it exactly
+    decremented: Vec<bool>,
+}
+
+impl PermutationPuzzle {
+    pub fn new(generators: GeneratorSet, difficulty: usize, depth_slope: usize, max_depth: usize) -> Result<Self> {
+        generators.validate()?;
+        let size = generators.generators[0].len();
+        Ok(PermutationPuzzle {
+            state: (0..size).collect(),
+            depth: 1,
+            generators,
+            difficulty,
+            depth_slope,
+            max_depth,
+            rng: StdRng::from_entropy(),
+            decremented: vec![],
+        })
+    }
+
+    pub fn from_config(config: &Value) -> Result<Self> {
+        let config = PermutationPuzzleConfig::deserialize(config)?;
+        let (difficulty, depth_slope, max_depth) = (config.difficulty, config.depth_slope, config.max_depth);
+        PermutationPuzzle::new(config.generator_set()?, difficulty, depth_slope, max_depth)
+    }
+
+    pub fn solved(&self) -> bool {
+        self.state.iter().enumerate().all(|(i, &v)| i == v)
+    }
+
+    fn size(&self) -> usize {
+        self.state.len()
+    }
+}
+
+impl Env for PermutationPuzzle {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn num_actions(&self) -> usize {
+        self.generators.generators.len()
+    }
+
+    fn obs_shape(&self) -> Vec<usize> {
+        vec![self.size(), self.size()]
+    }
+
+    fn action_name(&self, action: usize) -> String {
+        self.generators.names.get(action).cloned().unwrap_or_else(|| action.to_string())
+    }
+
+    // Feature i * size + v is set when the value v is at position i
+    fn obs_feature_name(&self, index: usize) -> String {
+        format!("{} at position {}", index % self.size(), index / self.size())
+    }
+
+    fn set_difficulty(&mut self, difficulty: usize) {
+        self.difficulty = difficulty;
+    }
+
+    fn get_difficulty(&self) -> usize {
+        self.difficulty
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.rng = seeded_rng(seed);
+    }
+
+    fn set_state(&mut self, state: Vec<i64>) {
+        self.state = state.iter().map(|&x| x as usize).collect();
+        self.depth = self.max_depth;
+        self.decremented.clear();
+    }
+
+    fn get_state(&self) -> Vec<i64> {
+        self.state.iter().map(|&x| x as i64).collect()
+    }
+
+    fn try_set_state(&mut self, state: Vec<i64>) -> EnvResult<()> {
+        let values: Vec<usize> = state.iter().map(|&x| x as usize).collect();
+        if state.iter().any(|&x| x < 0) || !is_permutation(&values, self.size()) {
+            return Err(EnvError::failed("set_state", format!("state must be a permutation of 0..{}, got {:?}", self.size(), state)));
+        }
+        self.set_state(state);
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.state = (0..self.size()).collect();
+        let action_range = Uniform::new(0, self.num_actions());
+        for _ in 0..self.difficulty {
+            let action = action_range.sample(&mut self.rng);
+            self.step(action);
+        }
+        self.depth = self.depth_slope * self.difficulty;
+        self.decremented.clear();
+    }
+
+    fn step(&mut self, action: usize) {
+        let generator = &self.generators.generators[action];
+        self.state = generator.iter().map(|&i| self.state[i]).collect();
+        self.decremented.push(self.depth > 0);
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    // Moves are undone by applying the inverse permutation
+    fn supports_undo(&self) -> bool {
+        true
+    }
+
+    fn undo(&mut self, action: usize) -> EnvResult<()> {
+        let Some(generator) = self.generators.generators.get(action) else {
+            return Err(EnvError::failed("undo", format!("action {} does not exist", action)));
+        };
+        let Some(decremented) = self.decremented.pop() else {
+            return Err(EnvError::failed("undo", "no step to undo since the last reset"));
+        };
+        let mut previous = vec![0; self.size()];
+        for (i, &g) in generator.iter().enumerate() {
+            previous[g] = self.state[i];
+        }
+        self.state = previous;
+        if decremented {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+
+    fn is_final(&self) -> bool {
+        self.depth == 0 || self.solved()
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.depth == 0 && !self.solved()
+    }
+
+    fn is_success(&self) -> bool {
+        self.solved()
+    }
+
+    fn reward(&self) -> f32 {
+        if self.solved() {
+            1.0
+        } else if self.depth == 0 {
+            -0.5
+        } else {
+            -0.5 / (self.max_depth as f32)
+        }
+    }
+
+    fn observe(&self) -> Vec<usize> {
+        self.state.iter().enumerate().map(|(i, v)| i * self.size() + v).collect()
+    }
+
+    // The identity, then the symmetries of the generator set. Values are relabeled like the positions,
+    // which keeps the goal in place.
+    fn twists(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let symmetries = &self.generators.symmetries;
+        if symmetries.is_empty() {
+            return (vec![], vec![]);
+        }
+        let size = self.size();
+        let identity = Symmetry { positions: (0..size).collect(), actions: (0..self.num_actions()).collect() };
+        std::iter::once(&identity).chain(symmetries.iter().filter(|&s| *s != identity))
+            .map(|s| {
+                let obs_perm = (0..size * size).map(|f| s.positions[f / size] * size + s.positions[f % size]).collect();
+                (obs_perm, s.actions.clone())
+            })
+            .unzip()
+    }
+}
+
+fn is_permutation(perm: &[usize], size: usize) -> bool {
+    let mut sorted = perm.to_vec();
+    sorted.sort();
+    sorted == (0..size).collect::<Vec<usize>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rl::env::testing::check_env;
+    use crate::rl::twists::check_twists;
+    use serde_json::json;
+
+    // Adjacent transpositions of 0..size, which are mirrored by reversing the positions
+    fn bubble_sort(size: usize) -> GeneratorSet {
+        let generators = (0..size - 1).map(|a| {
+            let mut g: Vec<usize> = (0..size).collect();
+            g.swap(a, a + 1);
+            g
+        }).collect();
+        let reverse = Symmetry { positions: (0..size).rev().collect(), actions: (0..size - 1).rev().collect() };
+        GeneratorSet { generators, names: vec![], symmetries: vec![reverse] }
+    }
+
+    #[test]
+    fn test_permutation_puzzle() {
+        // Pancake sorting: flip the first k + 2 values
+        let generators = (2..=4).map(|k| (0..k).rev().chain(k..4).collect()).collect();
+        let mut env = PermutationPuzzle::new(GeneratorSet::new(generators), 3, 2, 10).unwrap();
+        assert_eq!(env.num_actions(), 3);
+        assert_eq!(env.obs_shape(), vec![4, 4]);
+        env.set_state(vec![1, 2, 0, 3]);
+        env.step(1);
+        assert_eq!(env.get_state(), vec![0, 2, 1, 3]);
+        assert_eq!(env.observe(), vec![0, 6, 9, 15]);
+        env.undo(1).unwrap();
+        assert_eq!(env.get_state(), vec![1, 2, 0, 3]);
+        assert_eq!(env.depth, 10);
+        assert!(env.undo(1).is_err()); // nothing left to undo
+        // Steps past the end of the episode leave the depth at 0, and so do their undos
+        env.depth = 1;
+        env.step(0);
+        env.step(0);
+        env.undo(0).unwrap();
+        assert_eq!(env.depth, 0);
+        env.undo(0).unwrap();
+        assert_eq!((env.depth, env.get_state()), (1, vec![1, 2, 0, 3]));
+        env.set_state(vec![2, 1, 0, 3]);
+        env.step(1);
+        assert!(env.solved() && env.is_final() && env.reward() == 1.0);
+        assert!(env.try_set_state(vec![0, 0, 1, 2]).is_err());
+        check_env(&env, 5, 100, 0).unwrap();
+
+        // Rotations of a cycle have their inverse as the other action
+        let mut env = PermutationPuzzle::new(GeneratorSet::new(vec![vec![1, 2, 0], vec![2, 0, 1]]), 1, 1, 10).unwrap();
+        env.step(0);
+        assert_eq!(env.get_state(), vec![1, 2, 0]);
+        env.step(1);
+        assert!(env.solved());
+
+        assert!(PermutationPuzzle::new(GeneratorSet::new(vec![vec![0, 1], vec![0, 1, 2]]), 1, 1, 10).is_err());
+        assert!(PermutationPuzzle::new(GeneratorSet::new(vec![]), 1, 1, 10).is_err());
+    }
+
+    #[test]
+    fn test_permutation_puzzle_twists() {
+        let env = PermutationPuzzle::new(bubble_sort(5), 0, 1, 20).unwrap();
+        let (obs_perms, act_perms) = env.twists();
+        assert_eq!(act_perms, vec![vec![0, 1, 2, 3], vec![3, 2, 1, 0]]);
+        assert_eq!(obs_perms[1][0], 24);
+        assert_eq!(check_twists(&env, 20, 30, 0).unwrap(), None);
+
+        // Reversing the positions without the actions is not a symmetry
+        let mut generators = bubble_sort(5);
+        generators.symmetries[0].actions = (0..4).collect();
+        assert!(PermutationPuzzle::new(generators, 0, 1, 20).is_err());
+    }
+
+    #[test]
+    fn test_permutation_puzzle_config() {
+        let path = std::env::temp_dir().join("twisterl_test_generators.json");
+        fs::write(&path, r#"{"generators": [[1, 0, 2], [0, 2, 1]], "names": ["swap01", "swap12"]}"#).unwrap();
+        let env = PermutationPuzzle::from_config(&json!({"generators_file": path, "difficulty": 2, "depth_slope": 2, "max_depth": 8})).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(env.action_names(), vec!["swap01", "swap12"]);
+
+        let config = json!({"generators": [[1, 0, 2]], "difficulty": 2, "depth_slope": 2, "max_depth": 8});
+        assert_eq!(PermutationPuzzle::from_config(&config).unwrap().num_actions(), 1);
+        assert!(PermutationPuzzle::from_config(&json!({"difficulty": 2, "depth_slope": 2, "max_depth": 8})).is_err());
+    }
+}
@@ -11,8 +11,23 @@ copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use rand::distributions::{Distribution, Uniform};
-use crate::rl::env::Env;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::rl::env::{Env, EnvError, EnvResult, Observation};
+use crate::rl::rng::{derive_seed, seeded_rng};
+use crate::rl::vec_env::{VecEnv, VecStep, check_num_actions};
+use crate::envs::puzzle::pdb::PatternDatabase;
+use crate::envs::puzzle::solver::Solver;
+
+pub mod pdb;
+pub mod solver;
 
 
 // This is the Env definition
@@ -27,8 +42,69 @@ pub struct Puzzle {
     pub difficulty: usize,
     pub depth_slope: usize,
     pub max_depth: usize,
+
+    // Target permutation (the identity by default), scrambled by `reset`
+    pub goal: Vec<usize>,
+    // If set, the observation also encodes the goal, so one policy can reach any goal
+    pub goal_in_obs: bool,
+    // How `reset` picks the initial state
+    pub reset_mode: ResetMode,
+    // If set, `twists` returns the board symmetries. Off by default, as it changes the predictions of the trained policies.
+    pub use_twists: bool,
+
+    rng: StdRng,
+    // BFS layers of `ResetMode::ExactDistance` for each goal used so far, shared by the clones of the puzzle.
+    // The map is only locked to find the layers of a goal, so clones with different goals do not wait on each other.
+    distance_layers: Arc<DistanceLayersByGoal>,
+    // For each step since the last reset or set_state, whether it moved the blank and whether it decremented the
+    // depth, so that `undo` skips the masked moves and restores the depth exactly
+    steps: Vec<(bool, bool)>,
+}
+
+// The boards by optimal distance from a goal: `layers[d]` holds all the boards exactly `d` moves away.
+// Only the layers that were needed so far are computed.
+#[derive(Default)]
+struct DistanceLayers {
+    layers: Vec<Vec<Vec<usize>>>,
 }
 
+type DistanceLayersByGoal = Mutex<HashMap<Vec<usize>, Arc<Mutex<DistanceLayers>>>>;
+
+// How `Puzzle::reset` picks the initial state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetMode {
+    // `difficulty` random moves from the goal. Moves against a wall are no-ops, so the state is often closer than that.
+    #[default]
+    Scramble,
+    // Uniformly among all the states from which the goal can be reached. Their distance to the goal does not depend
+    // on `difficulty`, so the episodes get `max_depth` steps instead of `depth_slope * difficulty`.
+    Uniform,
+    // Uniformly among the states at an optimal distance of exactly `difficulty` moves from the goal.
+    // The states are enumerated by BFS, so this is only practical for small boards or distances.
+    // The BFS layers are cached by the puzzle and its clones, and `reset` fails if no state is that far.
+    ExactDistance,
+}
+
+
+// Config of a puzzle, with the same fields as the arguments of `Puzzle::new`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PuzzleConfig {
+    pub width: usize,
+    pub height: usize,
+    pub difficulty: usize,
+    pub depth_slope: usize,
+    pub max_depth: usize,
+    #[serde(default)]
+    pub goal: Option<Vec<usize>>,
+    #[serde(default)]
+    pub goal_in_obs: bool,
+    #[serde(default)]
+    pub reset_mode: ResetMode,
+    #[serde(default)]
+    pub use_twists: bool,
+}
 
 impl Puzzle {
     pub fn new(
@@ -38,34 +114,230 @@ impl Puzzle {
         depth_slope: usize,
         max_depth: usize,
     ) -> Self {
-        Puzzle {state: (0..(width*height)).collect(), zero_location: (0,0), depth:1, width, height, difficulty, depth_slope, max_depth}
+        Puzzle {state: (0..(width*height)).collect(), zero_location: (0,0), depth:1, width, height, difficulty, depth_slope, max_depth, goal: (0..(width*height)).collect(), goal_in_obs: false, reset_mode: ResetMode::Scramble, use_twists: false, rng: StdRng::from_entropy(), distance_layers: Arc::default(), steps: vec![]}
+    }
+
+    pub fn from_config(config: &Value) -> anyhow::Result<Self> {
+        let config = PuzzleConfig::deserialize(config)?;
+        let mut puzzle = Puzzle::new(config.width, config.height, config.difficulty, config.depth_slope, config.max_depth);
+        puzzle.goal_in_obs = config.goal_in_obs;
+        puzzle.reset_mode = config.reset_mode;
+        puzzle.use_twists = config.use_twists;
+        if let Some(goal) = config.goal {
+            puzzle.set_goal(goal.iter().map(|&v| v as i64).collect())?;
+        }
+        Ok(puzzle)
     }
 
     pub fn solved(&self) -> bool {
-        for i in 0..self.state.len() {
-            if self.state[i] != i {return false}
+        self.state == self.goal
+    }
+
+    // The goal can be reached iff the permutation from the goal to the board has the parity of the blank's
+    // Manhattan distance to its goal position, as every move is a transposition that moves the blank by one.
+    // For odd widths this is the usual inversion count rule, for even widths the one that adds the blank row.
+    pub fn is_solvable(&self, board: &[usize]) -> bool {
+        let mut goal_position = vec![0; board.len()];
+        for (i, &v) in self.goal.iter().enumerate() {
+            goal_position[v] = i;
+        }
+        // Parity of a permutation: number of elements minus number of cycles
+        let mut visited = vec![false; board.len()];
+        let mut transpositions = 0;
+        for start in 0..board.len() {
+            if visited[start] {
+                continue;
+            }
+            // A cycle of length k is k - 1 transpositions
+            let mut i = goal_position[board[start]];
+            visited[start] = true;
+            while i != start {
+                visited[i] = true;
+                i = goal_position[board[i]];
+                transpositions += 1;
+            }
+        }
+        let (bx, by) = self.blank_location(board);
+        let (gx, gy) = self.blank_location(&self.goal);
+        transpositions % 2 == (bx.abs_diff(gx) + by.abs_diff(gy)) % 2
+    }
+
+    // Returns a shortest sequence of actions from the current state to the goal (IDA*), or None if it is unsolvable.
+    // Fast for the 8-puzzle, can take long on hard 15-puzzle instances.
+    pub fn optimal_solution(&self) -> Option<Vec<usize>> {
+        if !self.is_solvable(&self.state) {
+            return None;
         }
+        Solver::new(self).solve(&self.state, usize::MAX)
+    }
 
-        true
+    // Same as optimal_solution, with the pattern database as an additional heuristic
+    pub fn optimal_solution_with(&self, pdb: &PatternDatabase) -> anyhow::Result<Option<Vec<usize>>> {
+        let solver = Solver::new(self).with_pattern_database(pdb)?;
+        if !self.is_solvable(&self.state) {
+            return Ok(None);
+        }
+        Ok(solver.solve(&self.state, usize::MAX))
     }
 
-    pub fn get_state(&self) -> Vec<usize> {
-        self.state.clone()
+    // Returns a uniformly random board from which the goal can be reached
+    fn sample_solvable(&mut self) -> Vec<usize> {
+        let mut board = self.goal.clone();
+        board.shuffle(&mut self.rng);
+        if !self.is_solvable(&board) {
+            // Swapping two tiles flips the parity
+            let mut tiles = (0..board.len()).filter(|&i| board[i] != 0);
+            let (i, j) = (tiles.next().unwrap(), tiles.next().unwrap());
+            board.swap(i, j);
+        }
+        board
     }
 
-    pub fn display(&self) {
-        for (i, &v) in self.state.iter().enumerate() {
-            if v == 0 {
-                print!("   ");
-            } else if v < 10 {
-                print!("  {} ", v);
-            } else {
-                print!(" {} ", v);
+    // Returns the boards reachable with one move
+    fn neighbors(&self, board: &[usize]) -> Vec<Vec<usize>> {
+        let (zx, zy) = self.blank_location(board);
+        let zero = zy * self.width + zx;
+        let mut moves = vec![];
+        if zx > 0 { moves.push(zero - 1); }
+        if zy > 0 { moves.push(zero - self.width); }
+        if zx < self.width - 1 { moves.push(zero + 1); }
+        if zy < self.height - 1 { moves.push(zero + self.width); }
+        moves.into_iter().map(|tile| {
+            let mut next = board.to_vec();
+            next.swap(zero, tile);
+            next
+        }).collect()
+    }
+
+    // Returns a uniformly random board at an optimal distance of exactly `distance` moves from the goal,
+    // or an error if no board is that far
+    fn sample_at_distance(&mut self, distance: usize) -> EnvResult<Vec<usize>> {
+        let cache = {
+            let mut caches = self.distance_layers.lock().unwrap_or_else(|err| err.into_inner());
+            Arc::clone(caches.entry(self.goal.clone()).or_default())
+        };
+        let mut cache = cache.lock().unwrap_or_else(|err| err.into_inner());
+        if cache.layers.is_empty() {
+            cache.layers.push(vec![self.goal.clone()]);
+        }
+
+        while cache.layers.len() <= distance {
+            // Moves change the parity of the blank position, so the neighbors of a layer are in the previous or the next one
+            let n = cache.layers.len();
+            let previous: HashSet<&Vec<usize>> = if n > 1 { cache.layers[n - 2].iter().collect() } else { HashSet::new() };
+            let mut seen = HashSet::new();
+            let mut next_layer = vec![];
+            for board in &cache.layers[n - 1] {
+                for next in self.neighbors(board) {
+                    if !previous.contains(&next) && seen.insert(next.clone()) {
+                        next_layer.push(next);
+                    }
+                }
             }
-            if (i+1)%self.width == 0 {
-                print!("\n")
+            if next_layer.is_empty() {
+                return Err(EnvError::failed("reset", format!(
+                    "no board is {} moves away from the goal, the farthest are {} moves away", distance, n - 1
+                )));
             }
+            cache.layers.push(next_layer);
+        }
+
+        let layer = &cache.layers[distance];
+        Ok(layer[self.rng.gen_range(0..layer.len())].clone())
+    }
+
+    // Moves the blank in the direction of `action`, returns false if the move is masked
+    fn move_blank(&mut self, action: usize) -> bool {
+        let (zx, zy) = self.zero_location;
+        if (action == 0) && (zx > 0) {
+            let new_val = self.get_position(zx-1, zy);
+            self.set_position(zx, zy, new_val);
+            self.set_position(zx-1, zy, 0);
+            self.zero_location = (zx-1, zy);
+        } else if (action == 1) && (zy > 0) {
+            let new_val = self.get_position(zx, zy-1);
+            self.set_position(zx, zy, new_val);
+            self.set_position(zx, zy-1, 0);
+            self.zero_location = (zx, zy-1);
+        } else if (action == 2) && (zx < (self.width-1)) {
+            let new_val = self.get_position(zx+1, zy);
+            self.set_position(zx, zy, new_val);
+            self.set_position(zx+1, zy, 0);
+            self.zero_location = (zx+1, zy);
+        } else if (action == 3) && (zy < (self.height-1)) {
+            let new_val = self.get_position(zx, zy+1);
+            self.set_position(zx, zy, new_val);
+            self.set_position(zx, zy+1, 0);
+            self.zero_location = (zx, zy+1);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    // Returns the (x, y) position of the blank in the given board
+    fn blank_location(&self, board: &[usize]) -> (usize, usize) {
+        let i = board.iter().position(|&v| v == 0).unwrap_or(0);
+        (i % self.width, i / self.width)
+    }
+
+    // Draws the board one row per line, e.g. "|8|7|5|". With ansi, tiles in their goal position are green.
+    fn render_board(&self, ansi: bool) -> String {
+        let padding = (self.state.len() - 1).to_string().len();
+        let mut txt = String::new();
+        for row in self.state.chunks(self.width) {
+            let y = txt.lines().count();
+            let cells: Vec<String> = row.iter().enumerate().map(|(x, &v)| {
+                let cell = if v == 0 { " ".repeat(padding) } else { format!("{:>padding$}", v) };
+                if ansi && v != 0 && v == self.goal[y * self.width + x] {
+                    format!("\x1b[32m{}\x1b[0m", cell)
+                } else {
+                    cell
+                }
+            }).collect();
+            txt += &format!("|{}|\n", cells.join("|"));
         }
+        txt
+    }
+
+    // Returns the symmetries of the board that are compatible with the goal, starting with the identity, as
+    // (cell permutation, action permutation) pairs: the tile in cell i goes to cell_perm[i], and moving the blank
+    // with action a becomes action act_perm[a]. Reflections are kept when they fix the goal cell of the blank,
+    // as the tiles can then be relabeled to fix the goal. With the goal in the observation, it is transformed too.
+    fn symmetries(&self) -> Vec<(Vec<usize>, Vec<usize>)> {
+        // Maps the cell (x, y) of a w x h board to its image
+        type CellMap = fn(i64, i64, i64, i64) -> (i64, i64);
+        let maps: [CellMap; 8] = [
+            |x, y, _, _| (x, y),
+            |x, y, w, _| (w - 1 - x, y),
+            |x, y, _, h| (x, h - 1 - y),
+            |x, y, w, h| (w - 1 - x, h - 1 - y),
+            // The others swap the axes, so they only apply to square boards
+            |x, y, _, _| (y, x),
+            |x, y, w, h| (h - 1 - y, w - 1 - x),
+            |x, y, _, h| (h - 1 - y, x),
+            |x, y, w, _| (y, w - 1 - x),
+        ];
+        let num_maps = if self.width == self.height { 8 } else { 4 };
+        let directions = [(-1, 0), (0, -1), (1, 0), (0, 1)];
+        let (w, h) = (self.width as i64, self.height as i64);
+        let blank_goal = self.goal.iter().position(|&v| v == 0).unwrap_or(0);
+
+        maps[..num_maps].iter().filter_map(|map| {
+            let cell_perm: Vec<usize> = (0..self.state.len()).map(|i| {
+                let (x, y) = map((i % self.width) as i64, (i / self.width) as i64, w, h);
+                (y * w + x) as usize
+            }).collect();
+            if !self.goal_in_obs && cell_perm[blank_goal] != blank_goal {
+                return None;
+            }
+            let (ox, oy) = map(0, 0, w, h);
+            let act_perm = directions.iter().map(|&(dx, dy)| {
+                let (x, y) = map(dx, dy, w, h);
+                directions.iter().position(|&d| d == (x - ox, y - oy)).unwrap()
+            }).collect();
+            Some((cell_perm, act_perm))
+        }).collect()
     }
 
     pub fn set_position(&mut self, x: usize, y: usize, val: usize) {
@@ -92,10 +364,56 @@ impl Env for Puzzle {
     }
 
     fn obs_shape(&self) -> Vec<usize> {
+        if self.goal_in_obs {
+            // One row per position of the board, then one per position of the goal
+            return vec![2 * self.state.len(), self.state.len()];
+        }
         vec![self.state.len(), self.state.len()]
         //vec![4, 6*self.tmax]
     }
 
+    fn set_goal(&mut self, goal: Vec<i64>) -> EnvResult<()> {
+        let size = self.state.len();
+        let mut sorted = goal.clone();
+        sorted.sort();
+        if sorted != (0..size as i64).collect::<Vec<i64>>() {
+            return Err(EnvError::failed("set_goal", format!("goal must be a permutation of 0..{}, got {:?}", size, goal)));
+        }
+        self.goal = goal.iter().map(|&v| v as usize).collect();
+        Ok(())
+    }
+
+    fn get_goal(&self) -> Option<Vec<i64>> {
+        Some(self.goal.iter().map(|&v| v as i64).collect())
+    }
+
+    fn render(&self) -> String {
+        self.render_board(false)
+    }
+
+    fn render_ansi(&self) -> String {
+        self.render_board(true)
+    }
+
+    // Actions move the blank
+    fn action_name(&self, action: usize) -> String {
+        ["left", "up", "right", "down"].get(action).map_or_else(|| action.to_string(), |name| name.to_string())
+    }
+
+    // Feature i * size + v is set when tile v is at position i (of the goal, for i >= size)
+    fn obs_feature_name(&self, index: usize) -> String {
+        let size = self.state.len();
+        let (position, tile) = (index / size, index % size);
+        let prefix = if position >= size { "goal: " } else { "" };
+        let position = position % size;
+        let (x, y) = (position % self.width, position / self.width);
+        if tile == 0 {
+            format!("{}blank at ({}, {})", prefix, x, y)
+        } else {
+            format!("{}tile {} at ({}, {})", prefix, tile, x, y)
+        }
+    }
+
     fn set_difficulty(&mut self, difficulty: usize) {
         self.difficulty = difficulty;
     }
@@ -104,9 +422,14 @@ impl Env for Puzzle {
         self.difficulty
     }
 
+    fn set_seed(&mut self, seed: u64) {
+        self.rng = seeded_rng(seed);
+    }
+
     fn set_state(&mut self, state: Vec<i64>) {
         self.state = state.iter().map(|&x| x as usize).collect();
         self.depth = self.max_depth;
+        self.steps.clear();
 
         for (i, &s) in state.iter().enumerate() {
             if s == 0 {
@@ -116,49 +439,74 @@ impl Env for Puzzle {
         }
     }
 
-    fn reset(&mut self) {
-        // Reset the state to the target
-        self.state = (0..(self.width * self.height)).collect();
-        self.zero_location = (0,0);
+    fn get_state(&self) -> Vec<i64> {
+        self.state.iter().map(|&x| x as i64).collect()
+    }
 
-        let mut rng = rand::thread_rng();
-        let action_range = Uniform::new(0, self.num_actions());
+    fn reset(&mut self) {
+        self.try_reset().unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        // Apply random actions based on the difficulty
-        for _ in 0..self.difficulty {
-            let action = action_range.sample(&mut rng);
-            self.step(action);
+    fn try_reset(&mut self) -> EnvResult<()> {
+        match self.reset_mode {
+            ResetMode::Scramble => {
+                // Reset the state to the target
+                self.state = self.goal.clone();
+                self.zero_location = self.blank_location(&self.goal);
+
+                let action_range = Uniform::new(0, self.num_actions());
+
+                // Apply random actions based on the difficulty
+                for _ in 0..self.difficulty {
+                    let action = action_range.sample(&mut self.rng);
+                    self.step(action);
+                }
+                self.depth = self.depth_slope * self.difficulty;
+            }
+            ResetMode::Uniform => {
+                self.state = self.sample_solvable();
+                self.zero_location = self.blank_location(&self.state);
+                // The difficulty is not used by uniform resets, see ResetMode::Uniform
+                self.depth = self.max_depth;
+            }
+            ResetMode::ExactDistance => {
+                self.state = self.sample_at_distance(self.difficulty)?;
+                self.zero_location = self.blank_location(&self.state);
+                self.depth = self.depth_slope * self.difficulty;
+            }
         }
-        self.depth = self.depth_slope * self.difficulty;  
+        self.steps.clear();
+        Ok(())
     }
 
     fn step(&mut self, action: usize)  {
-        let (zx, zy) = self.zero_location;
-        if (action == 0) && (zx > 0) {
-            let new_val = self.get_position(zx-1, zy);
-            self.set_position(zx, zy, new_val);
-            self.set_position(zx-1, zy, 0);
-            self.zero_location = (zx-1, zy);
-        } else if (action == 1) && (zy > 0) {
-            let new_val = self.get_position(zx, zy-1);
-            self.set_position(zx, zy, new_val);
-            self.set_position(zx, zy-1, 0);
-            self.zero_location = (zx, zy-1);
-        } else if (action == 2) && (zx < (self.width-1)) {
-            let new_val = self.get_position(zx+1, zy);
-            self.set_position(zx, zy, new_val);
-            self.set_position(zx+1, zy, 0);
-            self.zero_location = (zx+1, zy);
-        } else if (action == 3) && (zy < (self.height-1)) {
-            let new_val = self.get_position(zx, zy+1);
-            self.set_position(zx, zy, new_val);
-            self.set_position(zx, zy+1, 0);
-            self.zero_location = (zx, zy+1);
-        }
-
+        let moved = self.move_blank(action);
+        self.steps.push((moved, self.depth > 0));
         self.depth = self.depth.saturating_sub(1); 
     }
     
+    // Moves are undone by moving the blank back in the opposite direction
+    fn supports_undo(&self) -> bool {
+        true
+    }
+
+    fn undo(&mut self, action: usize) -> EnvResult<()> {
+        let (moved, decremented) = *self.steps.last().ok_or_else(|| EnvError::failed("undo", "no step to undo since the last reset"))?;
+        // Masked actions did not move the blank, so there is nothing to move back
+        if moved {
+            let inverse = (action + 2) % 4;
+            if action >= 4 || !self.masks()[inverse] {
+                return Err(EnvError::failed("undo", format!("action {} cannot be undone from this state", action)));
+            }
+            self.move_blank(inverse);
+        }
+        self.steps.pop();
+        if decremented {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+
     fn masks(&self) -> Vec<bool> {
         let (zx, zy) = self.zero_location;
         vec![(zx > 0), (zy > 0), (zx < (self.width-1)), (zy < (self.height-1))]
@@ -168,6 +516,14 @@ impl Env for Puzzle {
         self.depth == 0 || self.solved()
     }
 
+    fn is_truncated(&self) -> bool {
+        self.depth == 0 && !self.solved()
+    }
+
+    fn is_success(&self) -> bool {
+        self.solved()
+    }
+
     fn reward(&self) -> f32 {
         if self.solved() {
             1.0
@@ -177,11 +533,129 @@ impl Env for Puzzle {
     }
 
 fn observe(&self,) -> Vec<usize> {
-        self.state.iter().enumerate().map(|(i, v)| i * self.height * self.width + v).collect()
+        let mut obs: Vec<usize> = self.state.iter().enumerate().map(|(i, v)| i * self.height * self.width + v).collect();
+        if self.goal_in_obs {
+            let size = self.state.len();
+            obs.extend(self.goal.iter().enumerate().map(|(i, v)| (size + i) * size + v));
+        }
+        obs
+    }
+
+    fn vec_env(&self, num_envs: usize) -> Option<Box<dyn VecEnv>> {
+        Some(Box::new(PuzzleVecEnv::new(self, num_envs)))
+    }
+
+    // The remaining depth is not part of the key, only the board (and the goal, when it is observed)
+    fn state_key(&self) -> Vec<i64> {
+        let mut key: Vec<i64> = self.state.iter().map(|&v| v as i64).collect();
+        if self.goal_in_obs {
+            key.extend(self.goal.iter().map(|&v| v as i64));
+        }
+        key
+    }
+
+    // Board symmetries if `use_twists` is set, see Puzzle::symmetries. The tiles are relabeled so that the goal is
+    // unchanged, unless the goal is observed, in which case it is moved like the board.
+    fn twists(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let symmetries = self.symmetries();
+        if !self.use_twists || symmetries.len() < 2 {
+            return (vec![], vec![]);
+        }
+        let size = self.state.len();
+        let num_rows = self.obs_shape()[0];
+        symmetries.into_iter().map(|(cell_perm, act_perm)| {
+            // The tile v in the goal goes where the goal has relabel[v]
+            let mut relabel: Vec<usize> = (0..size).collect();
+            if !self.goal_in_obs {
+                for (i, &v) in self.goal.iter().enumerate() {
+                    relabel[v] = self.goal[cell_perm[i]];
+                }
+            }
+            // Feature row * size + v is the value v in a cell of the board (or of the goal, for the rows after size)
+            let obs_perm = (0..num_rows * size).map(|f| {
+                let (row, v) = (f / size, f % size);
+                ((row / size) * size + cell_perm[row % size]) * size + relabel[v]
+            }).collect();
+            (obs_perm, act_perm)
+        }).unzip()
     }
 
 }
 
+// Fast path VecEnv for puzzles, which calls the puzzle methods directly instead of through `dyn Env`
+#[derive(Clone)]
+pub struct PuzzleVecEnv {
+    envs: Vec<Puzzle>,
+}
+
+impl PuzzleVecEnv {
+    pub fn new(puzzle: &Puzzle, num_envs: usize) -> Self {
+        PuzzleVecEnv { envs: vec![puzzle.clone(); num_envs] }
+    }
+}
+
+impl VecEnv for PuzzleVecEnv {
+    fn num_envs(&self) -> usize {
+        self.envs.len()
+    }
+
+    fn num_actions(&self) -> usize {
+        4
+    }
+
+    fn obs_shape(&self) -> Vec<usize> {
+        self.envs[0].obs_shape()
+    }
+
+    fn set_difficulty(&mut self, difficulty: usize) {
+        for env in self.envs.iter_mut() {
+            env.set_difficulty(difficulty);
+        }
+    }
+
+    fn set_seed(&mut self, seed: u64) -> EnvResult<()> {
+        for (i, env) in self.envs.iter_mut().enumerate() {
+            env.set_seed(derive_seed(seed, i as u64));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> EnvResult<()> {
+        for env in self.envs.iter_mut() {
+            env.try_reset()?;
+        }
+        Ok(())
+    }
+
+    fn observations(&self) -> EnvResult<Vec<Observation>> {
+        Ok(self.envs.iter().map(|env| Observation::Sparse(env.observe())).collect())
+    }
+
+    fn masks(&self) -> EnvResult<Vec<Vec<bool>>> {
+        Ok(self.envs.iter().map(|env| env.masks()).collect())
+    }
+
+    fn step(&mut self, actions: &[usize]) -> EnvResult<VecStep> {
+        check_num_actions(self.envs.len(), actions)?;
+        let mut out = VecStep::with_capacity(self.envs.len());
+        for (env, &action) in self.envs.iter_mut().zip(actions.iter()) {
+            env.step(action);
+            let done = env.is_final();
+            out.rewards.push(env.reward());
+            out.successes.push(env.is_success());
+            out.dones.push(done);
+            out.truncated.push(env.is_truncated());
+            if done {
+                out.final_observations.push(Some(Observation::Sparse(env.observe())));
+                env.try_reset()?;
+            } else {
+                out.final_observations.push(None);
+            }
+        }
+        Ok(out)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +666,21 @@ mod tests {
         assert!(puzzle.solved());
     }
 
+    #[test]
+    fn test_puzzle_truncated() {
+        let mut puzzle = Puzzle::new(2, 2, 0, 1, 10);
+        puzzle.set_state(vec![1, 0, 2, 3]);
+        puzzle.depth = 1;
+        assert!(!puzzle.is_final());
+        puzzle.step(0); // back to the solved state on the last step
+        assert!(puzzle.is_terminated() && !puzzle.is_truncated());
+
+        puzzle.set_state(vec![1, 0, 2, 3]);
+        puzzle.depth = 1;
+        puzzle.step(3); // runs out of depth without solving
+        assert!(puzzle.is_final() && puzzle.is_truncated());
+    }
+
     #[test]
     fn test_puzzle_step_and_masks() {
         let mut puzzle = Puzzle::new(2, 2, 0, 1, 10);
@@ -199,4 +688,245 @@ mod tests {
         assert_eq!(puzzle.zero_location, (1, 0));
         assert_eq!(puzzle.masks(), vec![true, false, false, true]);
     }
+
+    #[test]
+    fn test_puzzle_undo() {
+        let mut puzzle = Puzzle::new(3, 3, 0, 1, 10);
+        puzzle.depth = 5; // runs out of depth on the last step
+        let start = puzzle.clone();
+        let actions = [2, 3, 0, 3, 2];
+        for &action in actions.iter() {
+            puzzle.step(action);
+        }
+        for &action in actions.iter().rev() {
+            puzzle.undo(action).unwrap();
+        }
+        assert!(puzzle.same_state(&start));
+        assert_eq!(puzzle.depth, 5);
+        assert!(puzzle.undo(2).is_err()); // nothing left to undo
+
+        // Masked steps do not move the blank, and neither does their undo
+        puzzle.step(2);
+        assert!(puzzle.undo(3).is_err()); // the blank can not have moved down to the top row
+        puzzle.step(1); // against the top wall
+        puzzle.undo(1).unwrap();
+        assert_eq!(puzzle.zero_location, (1, 0));
+        puzzle.undo(2).unwrap();
+        assert!(puzzle.same_state(&start));
+        assert_eq!(puzzle.depth, 5);
+
+        // Steps past the end of the episode leave the depth at 0, and so do their undos
+        puzzle.depth = 1;
+        puzzle.step(2);
+        puzzle.step(0);
+        assert_eq!(puzzle.depth, 0);
+        puzzle.undo(0).unwrap();
+        assert_eq!(puzzle.depth, 0);
+        puzzle.undo(2).unwrap();
+        assert_eq!(puzzle.depth, 1);
+    }
+
+    #[test]
+    fn test_puzzle_goal() {
+        let mut puzzle = Puzzle::new(2, 2, 3, 1, 10);
+        assert!(puzzle.set_goal(vec![0, 1, 1, 3]).is_err());
+        puzzle.set_goal(vec![1, 0, 2, 3]).unwrap();
+        puzzle.set_seed(0);
+        puzzle.reset();
+        assert_eq!(puzzle.zero_location, puzzle.blank_location(&puzzle.state));
+
+        puzzle.set_state(vec![1, 0, 2, 3]);
+        assert!(puzzle.solved() && puzzle.is_success());
+        puzzle.set_state(vec![0, 1, 2, 3]);
+        assert!(!puzzle.solved());
+
+        puzzle.goal_in_obs = true;
+        assert_eq!(puzzle.obs_shape(), vec![8, 4]);
+        assert_eq!(puzzle.observe(), vec![0, 5, 10, 15, 17, 20, 26, 31]);
+    }
+
+    #[test]
+    fn test_puzzle_render() {
+        let mut puzzle = Puzzle::new(3, 3, 0, 1, 10);
+        puzzle.step(2);
+        assert_eq!(puzzle.render(), "|1| |2|\n|3|4|5|\n|6|7|8|\n");
+        assert!(puzzle.render_ansi().contains("\x1b[32m3\x1b[0m"));
+    }
+
+    #[test]
+    fn test_puzzle_names() {
+        let mut puzzle = Puzzle::new(3, 2, 0, 1, 10);
+        assert_eq!(puzzle.action_names(), vec!["left", "up", "right", "down"]);
+        puzzle.step(2);
+        let names: Vec<String> = puzzle.observe().iter().map(|&i| puzzle.obs_feature_name(i)).collect();
+        assert_eq!(names[0], "tile 1 at (0, 0)");
+        assert_eq!(names[1], "blank at (1, 0)");
+    }
+
+    #[test]
+    fn test_puzzle_solvability() {
+        for (width, height) in [(3, 3), (4, 4), (3, 2)] {
+            let mut puzzle = Puzzle::new(width, height, 30, 1, 40);
+            puzzle.set_seed(1);
+            for _ in 0..10 {
+                puzzle.reset();
+                assert!(puzzle.is_solvable(&puzzle.state));
+                let mut swapped = puzzle.state.clone();
+                let tiles: Vec<usize> = (0..swapped.len()).filter(|&i| swapped[i] != 0).take(2).collect();
+                swapped.swap(tiles[0], tiles[1]);
+                assert!(!puzzle.is_solvable(&swapped));
+            }
+        }
+    }
+
+    #[test]
+    fn test_puzzle_reset_modes() {
+        let mut puzzle = Puzzle::new(4, 4, 0, 1, 40);
+        puzzle.set_goal((0..16).rev().collect()).unwrap();
+        puzzle.reset_mode = ResetMode::Uniform;
+        puzzle.set_seed(2);
+        puzzle.reset();
+        let first = puzzle.state.clone();
+        for _ in 0..10 {
+            assert!(puzzle.is_solvable(&puzzle.state));
+            assert_eq!(puzzle.zero_location, puzzle.blank_location(&puzzle.state));
+            puzzle.reset();
+        }
+        assert_ne!(puzzle.state, first);
+
+        // Boards within 4 moves of the goal
+        let mut puzzle = Puzzle::new(3, 3, 5, 1, 10);
+        let mut near = HashSet::from([puzzle.goal.clone()]);
+        for _ in 0..4 {
+            near = near.iter().flat_map(|board| puzzle.neighbors(board)).chain(near.iter().cloned()).collect();
+        }
+        puzzle.reset_mode = ResetMode::ExactDistance;
+        puzzle.set_seed(3);
+        for _ in 0..10 {
+            puzzle.reset();
+            assert!(!near.contains(&puzzle.state));
+            assert!(puzzle.neighbors(&puzzle.state).iter().any(|board| near.contains(board)));
+            assert_eq!(puzzle.depth, 5);
+        }
+    }
+
+    #[test]
+    fn test_puzzle_exact_distance_cache() {
+        // The solvable 2x2 boards are at most 6 moves from the goal
+        let mut puzzle = Puzzle::new(2, 2, 6, 1, 10);
+        puzzle.reset_mode = ResetMode::ExactDistance;
+        puzzle.set_seed(1);
+        puzzle.try_reset().unwrap();
+        let clone = puzzle.clone();
+        let num_layers = |puzzle: &Puzzle, goal: &[usize]| puzzle.distance_layers.lock().unwrap()[goal].lock().unwrap().layers.len();
+        assert_eq!(num_layers(&clone, &[0, 1, 2, 3]), 7);
+
+        puzzle.difficulty = 7;
+        let err = puzzle.try_reset().unwrap_err();
+        assert!(err.to_string().contains("farthest are 6 moves away"));
+
+        // Each goal has its own layers, and the ones of the previous goal are kept
+        puzzle.difficulty = 1;
+        puzzle.set_goal(vec![1, 0, 2, 3]).unwrap();
+        puzzle.try_reset().unwrap();
+        assert_eq!(num_layers(&clone, &[1, 0, 2, 3]), 2);
+        assert_eq!(num_layers(&clone, &[0, 1, 2, 3]), 7);
+        assert_eq!(puzzle.neighbors(&puzzle.state).len(), 2);
+        assert!(puzzle.neighbors(&puzzle.state).contains(&vec![1, 0, 2, 3]));
+    }
+
+    #[test]
+    fn test_puzzle_seeded_reset() {
+        let mut p1 = Puzzle::new(3, 3, 20, 1, 40);
+        let mut p2 = Puzzle::new(3, 3, 20, 1, 40);
+        p1.set_seed(42);
+        p2.set_seed(42);
+        p1.reset();
+        p2.reset();
+        assert_eq!(p1.get_state(), p2.get_state());
+
+        let mut p3 = Puzzle::new(3, 3, 0, 1, 40);
+        p3.set_state(p1.get_state());
+        assert!(p3.same_state(&p1));
+    }
+
+    #[test]
+    fn test_puzzle_state_key() {
+        let mut p1 = Puzzle::new(2, 2, 0, 1, 10);
+        let mut p2 = Puzzle::new(2, 2, 0, 1, 10);
+        p1.step(2);
+        p2.depth = 5;
+        assert!(!p1.same_state(&p2));
+        p1.step(0); // back to the solved state
+        assert!(p1.same_state(&p2));
+        assert_eq!(p1.state_hash(), p2.state_hash());
+        assert_eq!(p1.state_key(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_puzzle_twists() {
+        use crate::rl::twists::check_twists;
+
+        // The twists are opt-in
+        let mut puzzle = Puzzle::new(3, 3, 0, 1, 20);
+        assert_eq!(puzzle.twists(), (vec![], vec![]));
+
+        // The blank goes to the top left corner, which only the transpose keeps in place.
+        // Starting from the goal resets the depth, so the walks are not cut short.
+        puzzle.use_twists = true;
+        puzzle.set_state(puzzle.get_goal().unwrap());
+        let (obs_perms, act_perms) = puzzle.twists();
+        assert_eq!(act_perms, vec![vec![0, 1, 2, 3], vec![1, 0, 3, 2]]);
+        assert_eq!(obs_perms[1][9 + 1], 3 * 9 + 3);
+        assert_eq!(check_twists(&puzzle, 20, 30, 0).unwrap(), None);
+        let mut puzzle = Puzzle::new(3, 2, 0, 1, 20);
+        puzzle.use_twists = true;
+        assert_eq!(puzzle.twists(), (vec![], vec![]));
+
+        // With the blank in the middle, all the symmetries of the square apply (or the left-right one of a 3x2 board)
+        for (width, height, goal, num_twists) in [(3, 3, vec![1, 2, 3, 4, 0, 5, 6, 7, 8], 8), (3, 2, vec![1, 0, 2, 3, 4, 5], 2)] {
+            let mut puzzle = Puzzle::new(width, height, 0, 1, 20);
+            puzzle.use_twists = true;
+            puzzle.set_goal(goal.iter().map(|&v| v as i64).collect()).unwrap();
+            puzzle.set_state(puzzle.get_goal().unwrap());
+            assert_eq!(puzzle.twists().0.len(), num_twists);
+            assert_eq!(check_twists(&puzzle, 20, 30, 0).unwrap(), None);
+        }
+
+        // With the goal in the observation, the twin env has the moved board and goal
+        let mut puzzle = Puzzle::new(3, 3, 10, 1, 20);
+        puzzle.goal_in_obs = true;
+        puzzle.use_twists = true;
+        puzzle.set_seed(0);
+        puzzle.reset();
+        let (obs_perms, act_perms) = puzzle.twists();
+        assert_eq!(obs_perms.len(), 8);
+        let mut rng = seeded_rng(0);
+        for ((cell_perm, _), (obs_perm, act_perm)) in puzzle.symmetries().iter().zip(obs_perms.iter().zip(act_perms.iter())) {
+            let moved = |board: &[usize]| {
+                let mut moved = vec![0; board.len()];
+                for (i, &v) in board.iter().enumerate() {
+                    moved[cell_perm[i]] = v as i64;
+                }
+                moved
+            };
+            let mut env = puzzle.clone();
+            let mut twin = puzzle.clone();
+            twin.set_goal(moved(&env.goal)).unwrap();
+            twin.set_state(moved(&env.state));
+            for _ in 0..20 {
+                let mut expected = Observation::Sparse(env.observe()).permute(obs_perm).to_weighted();
+                let mut observed = twin.observation().to_weighted();
+                expected.sort_by_key(|&(i, _)| i);
+                observed.sort_by_key(|&(i, _)| i);
+                assert_eq!(expected, observed);
+                let action = rng.gen_range(0..4);
+                assert_eq!(env.masks()[action], twin.masks()[act_perm[action]]);
+                if env.masks()[action] {
+                    env.step(action);
+                    twin.step(act_perm[action]);
+                }
+            }
+        }
+    }
 }
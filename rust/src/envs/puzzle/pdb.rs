@@ -0,0 +1,391 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+
+use crate::envs::puzzle::Puzzle;
+
+// Marks the abstract states that have not been reached yet
+const UNREACHED: u8 = u8::MAX;
+
+// Start of the files written by save, followed by the format version
+const MAGIC: &[u8; 4] = b"TPDB";
+const VERSION: u8 = 1;
+
+// Additive pattern database: for each pattern (a disjoint subset of the tiles), the minimal number of moves
+// of the pattern tiles needed to bring them to their goal positions, whatever the other tiles are.
+// Moves of the other tiles are free, so the sum over the patterns is an admissible heuristic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternDatabase {
+    pub width: usize,
+    pub height: usize,
+    pub goal: Vec<usize>,
+    pub patterns: Vec<Vec<usize>>,
+    // For each pattern, distances indexed by the rank of the positions of its tiles
+    tables: Vec<Vec<u8>>,
+}
+
+impl PatternDatabase {
+    // Builds the tables by a BFS from the goal of the puzzle, one pattern at a time (in parallel).
+    // A pattern of k tiles on n cells has n!/(n-k)! entries, and the BFS goes over n!/(n-k-1)! states.
+    pub fn build(puzzle: &Puzzle, patterns: Vec<Vec<usize>>) -> Result<Self> {
+        validate_patterns(puzzle.width * puzzle.height, &patterns)?;
+        let pdb = PatternDatabase {
+            width: puzzle.width,
+            height: puzzle.height,
+            goal: puzzle.goal.clone(),
+            patterns,
+            tables: vec![],
+        };
+        let tables = pdb.patterns.par_iter().map(|pattern| pdb.build_table(pattern)).collect::<Result<Vec<_>>>()?;
+        Ok(PatternDatabase { tables, ..pdb })
+    }
+
+    // 0-1 BFS over the positions of the pattern tiles and of the blank, where moving a pattern tile costs 1
+    // and moving any other tile is free. The table keeps the minimum over the positions of the blank.
+    fn build_table(&self, pattern: &[usize]) -> Result<Vec<u8>> {
+        let size = self.goal.len();
+        let blank = pattern.len();
+        let mut start: Vec<usize> = pattern.iter().map(|&tile| self.goal_position(tile)).collect();
+        start.push(self.goal_position(0));
+
+        let mut distances = vec![UNREACHED; num_partial_permutations(size, blank + 1)];
+        let mut queue = VecDeque::from([(rank(&start, size), 0)]);
+        distances[rank(&start, size)] = 0;
+        while let Some((index, distance)) = queue.pop_front() {
+            if distances[index] < distance {
+                continue;
+            }
+            let positions = unrank(index, blank + 1, size);
+            for next in self.neighbors(positions[blank]) {
+                let mut next_positions = positions.clone();
+                next_positions[blank] = next;
+                // The blank swaps with the tile at next, which is either a pattern tile or a free one
+                let moved = positions[..blank].iter().position(|&p| p == next);
+                let cost = match moved {
+                    Some(tile) => {
+                        next_positions[tile] = positions[blank];
+                        1
+                    }
+                    None => 0,
+                };
+                let next_distance = distance + cost;
+                if next_distance == UNREACHED {
+                    bail!("pattern {:?} has distances above {}", pattern, UNREACHED - 1);
+                }
+                let next_index = rank(&next_positions, size);
+                if next_distance < distances[next_index] {
+                    distances[next_index] = next_distance;
+                    if cost == 0 {
+                        queue.push_front((next_index, next_distance));
+                    } else {
+                        queue.push_back((next_index, next_distance));
+                    }
+                }
+            }
+        }
+
+        // Every placement of the pattern tiles is reachable, as the blank can go around
+        let mut table = vec![UNREACHED; num_partial_permutations(size, blank)];
+        for (index, &distance) in distances.iter().enumerate() {
+            let positions = unrank(index, blank + 1, size);
+            let entry = &mut table[rank(&positions[..blank], size)];
+            *entry = (*entry).min(distance);
+        }
+        Ok(table)
+    }
+
+    // Admissible estimate of the number of moves from the board to the goal: the sum of the pattern distances.
+    // Fails unless the board is a permutation of 0..width*height.
+    pub fn heuristic(&self, board: &[usize]) -> Result<usize> {
+        let size = self.width * self.height;
+        if !is_permutation(board, size) {
+            bail!("board must be a permutation of 0..{}, got {:?}", size, board);
+        }
+        Ok(self.heuristic_unchecked(board))
+    }
+
+    // Same as heuristic, for boards that are known to be permutations of 0..width*height (e.g. in the solver)
+    pub(crate) fn heuristic_unchecked(&self, board: &[usize]) -> usize {
+        (0..self.patterns.len()).map(|i| self.lookup(i, board)).sum()
+    }
+
+    // Distance of the i-th pattern in the board. Same as rank on the cells of the pattern tiles, without allocating.
+    fn lookup(&self, i: usize, board: &[usize]) -> usize {
+        let n = board.len();
+        let mut index = 0;
+        let mut used: u64 = 0;
+        for (j, &tile) in self.patterns[i].iter().enumerate() {
+            let p = board.iter().position(|&t| t == tile).unwrap();
+            let smaller_used = (used & ((1 << p) - 1)).count_ones() as usize;
+            index = index * (n - j) + p - smaller_used;
+            used |= 1 << p;
+        }
+        self.tables[i][index] as usize
+    }
+
+    // Whether the tables were built for this board size and goal
+    pub fn matches(&self, puzzle: &Puzzle) -> bool {
+        self.width == puzzle.width && self.height == puzzle.height && self.goal == puzzle.goal
+    }
+
+    // Binary format: MAGIC, VERSION, then one byte each for the width, the height, the goal tiles, the number of
+    // patterns and, for each pattern, its length and tiles, followed by the raw tables (sized by the patterns)
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let pdb = Self::from_bytes(&data).with_context(|| format!("{} is not a valid pattern database", path.display()))?;
+        Ok(pdb)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let byte = |value: usize| u8::try_from(value).with_context(|| format!("{} does not fit the file format", value));
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend([byte(self.width)?, byte(self.height)?]);
+        for &tile in &self.goal {
+            bytes.push(byte(tile)?);
+        }
+        bytes.push(byte(self.patterns.len())?);
+        for pattern in &self.patterns {
+            bytes.push(byte(pattern.len())?);
+            for &tile in pattern {
+                bytes.push(byte(tile)?);
+            }
+        }
+        for table in &self.tables {
+            bytes.extend_from_slice(table);
+        }
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("missing pattern database header");
+        }
+        let version = reader.byte()?;
+        if version != VERSION as usize {
+            bail!("unsupported format version {}, expected {}", version, VERSION);
+        }
+        let (width, height) = (reader.byte()?, reader.byte()?);
+        let goal = reader.take(width * height)?.iter().map(|&tile| tile as usize).collect();
+        let num_patterns = reader.byte()?;
+        let mut patterns = Vec::with_capacity(num_patterns);
+        for _ in 0..num_patterns {
+            let len = reader.byte()?;
+            patterns.push(reader.take(len)?.iter().map(|&tile| tile as usize).collect::<Vec<usize>>());
+        }
+        // The table sizes follow from the patterns, which have to be checked first
+        let pdb = PatternDatabase { width, height, goal, patterns, tables: vec![] };
+        pdb.validate()?;
+        let size = width * height;
+        let tables = pdb.patterns.iter()
+            .map(|pattern| Ok(reader.take(num_partial_permutations(size, pattern.len()))?.to_vec()))
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+        if !reader.bytes.is_empty() {
+            bail!("{} unexpected bytes after the tables", reader.bytes.len());
+        }
+        Ok(PatternDatabase { tables, ..pdb })
+    }
+
+    // Checks the invariants of the goal and patterns that build guarantees, for databases read from files
+    fn validate(&self) -> Result<()> {
+        let size = self.width * self.height;
+        if size < 2 {
+            bail!("boards must have at least 2 cells, got {}x{}", self.width, self.height);
+        }
+        if !is_permutation(&self.goal, size) {
+            bail!("goal must be a permutation of 0..{}, got {:?}", size, self.goal);
+        }
+        validate_patterns(size, &self.patterns)
+    }
+
+    fn goal_position(&self, tile: usize) -> usize {
+        self.goal.iter().position(|&t| t == tile).unwrap()
+    }
+
+    // Cells next to the given one
+    fn neighbors(&self, cell: usize) -> impl Iterator<Item = usize> {
+        let (x, y, width, height) = (cell % self.width, cell / self.width, self.width, self.height);
+        [
+            (x > 0).then(|| cell - 1),
+            (y > 0).then(|| cell - width),
+            (x < width - 1).then_some(cell + 1),
+            (y < height - 1).then_some(cell + width),
+        ].into_iter().flatten()
+    }
+}
+
+// Reads the fields of a saved database in order
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("the file is truncated");
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<usize> {
+        Ok(self.take(1)?[0] as usize)
+    }
+}
+
+// Checks that the patterns are non-empty disjoint sets of tiles other than the blank, so they are additive
+fn validate_patterns(size: usize, patterns: &[Vec<usize>]) -> Result<()> {
+    // lookup keeps the used cells in a u64
+    if size > 64 {
+        bail!("pattern databases support boards of up to 64 cells, got {}", size);
+    }
+    let mut seen = vec![false; size];
+    for pattern in patterns {
+        if pattern.is_empty() || pattern.len() >= size {
+            bail!("patterns must have between 1 and {} tiles, got {:?}", size - 1, pattern);
+        }
+        for &tile in pattern {
+            if tile == 0 || tile >= size {
+                bail!("pattern tiles must be in 1..{}, got {:?}", size, pattern);
+            }
+            if seen[tile] {
+                bail!("tile {} appears in several patterns, so they are not additive", tile);
+            }
+            seen[tile] = true;
+        }
+    }
+    Ok(())
+}
+
+fn is_permutation(board: &[usize], size: usize) -> bool {
+    let mut sorted = board.to_vec();
+    sorted.sort();
+    sorted == (0..size).collect::<Vec<usize>>()
+}
+
+// Number of ways to place k distinct items on n cells
+fn num_partial_permutations(n: usize, k: usize) -> usize {
+    (n - k + 1..=n).product()
+}
+
+// Index in 0..n!/(n-k)! of the distinct positions (in 0..n) of k items, in mixed radix n, n-1, ...
+fn rank(positions: &[usize], n: usize) -> usize {
+    let mut index = 0;
+    for (i, &p) in positions.iter().enumerate() {
+        let smaller_used = positions[..i].iter().filter(|&&q| q < p).count();
+        index = index * (n - i) + p - smaller_used;
+    }
+    index
+}
+
+// Inverse of rank
+fn unrank(mut index: usize, k: usize, n: usize) -> Vec<usize> {
+    let mut digits = vec![0; k];
+    for i in (0..k).rev() {
+        digits[i] = index % (n - i);
+        index /= n - i;
+    }
+    let mut used = vec![false; n];
+    digits.iter().map(|&digit| {
+        let p = (0..n).filter(|&p| !used[p]).nth(digit).unwrap();
+        used[p] = true;
+        p
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::ResetMode;
+    use crate::envs::puzzle::solver::Solver;
+    use crate::rl::env::Env;
+
+    #[test]
+    fn test_rank() {
+        for index in 0..num_partial_permutations(5, 3) {
+            assert_eq!(rank(&unrank(index, 3, 5), 5), index);
+        }
+        assert_eq!(num_partial_permutations(5, 3), 60);
+    }
+
+    #[test]
+    fn test_pattern_database() {
+        let mut puzzle = Puzzle::new(3, 3, 14, 1, 30);
+        puzzle.set_goal(vec![1, 2, 3, 4, 5, 6, 7, 8, 0]).unwrap();
+        let pdb = PatternDatabase::build(&puzzle, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]).unwrap();
+        // With all the tiles in a single pattern, the database is exact
+        let exact = PatternDatabase::build(&puzzle, vec![(1..9).collect()]).unwrap();
+        let solver = Solver::new(&puzzle);
+
+        puzzle.reset_mode = ResetMode::Uniform;
+        puzzle.set_seed(0);
+        for _ in 0..5 {
+            puzzle.reset();
+            let distance = puzzle.optimal_solution().unwrap().len();
+            assert_eq!(exact.heuristic(&puzzle.state).unwrap(), distance);
+            assert!(pdb.heuristic(&puzzle.state).unwrap() <= distance);
+            assert!(pdb.heuristic(&puzzle.state).unwrap() >= solver.manhattan(&puzzle.state));
+        }
+        assert_eq!(pdb.heuristic(&puzzle.goal).unwrap(), 0);
+        // Boards of another size, or with repeated tiles, are rejected
+        assert!(pdb.heuristic(&[1, 2, 3, 0]).is_err());
+        assert!(pdb.heuristic(&(0..16).collect::<Vec<usize>>()).is_err());
+        assert!(pdb.heuristic(&[1, 1, 3, 4, 5, 6, 7, 8, 0]).is_err());
+
+        let path = std::env::temp_dir().join("twisterl_test_pattern_database.pdb");
+        pdb.save(&path).unwrap();
+        assert_eq!(PatternDatabase::load(&path).unwrap(), pdb);
+        // The tables are stored as raw bytes after a short header
+        let table_size: usize = pdb.tables.iter().map(|table| table.len()).sum();
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.len() < table_size + 32);
+
+        // Truncated files, trailing bytes and other formats are rejected
+        for bad in [&data[..data.len() - 1], &[data.as_slice(), &[0]].concat(), b"{\"width\": 3}".as_slice()] {
+            std::fs::write(&path, bad).unwrap();
+            assert!(PatternDatabase::load(&path).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(PatternDatabase::build(&puzzle, vec![vec![1, 2], vec![2, 3]]).is_err());
+        assert!(PatternDatabase::build(&puzzle, vec![vec![0, 1]]).is_err());
+
+        // Files that break the invariants of build are rejected
+        let invalid = [
+            PatternDatabase { goal: vec![1, 1, 3, 4, 5, 6, 7, 8, 0], ..pdb.clone() },
+            PatternDatabase { patterns: vec![vec![1, 2, 3, 4], vec![4, 6, 7, 8]], ..pdb.clone() },
+            PatternDatabase { patterns: vec![vec![0, 2, 3, 4], vec![5, 6, 7, 8]], ..pdb.clone() },
+            PatternDatabase { patterns: vec![vec![], vec![1, 2, 3, 4]], tables: vec![vec![0], pdb.tables[0].clone()], ..pdb.clone() },
+            PatternDatabase { width: 0, goal: vec![], ..pdb.clone() },
+        ];
+        for pdb in invalid {
+            pdb.save(&path).unwrap();
+            assert!(PatternDatabase::load(&path).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use crate::envs::puzzle::Puzzle;
+use crate::envs::puzzle::pdb::PatternDatabase;
+
+// Optimal solver for the sliding puzzle, based on IDA* with an admissible heuristic
+pub struct Solver<'a> {
+    width: usize,
+    height: usize,
+    // Index of each tile in the goal board
+    goal_position: Vec<usize>,
+    // Optional additive pattern database, for a stronger heuristic
+    pdb: Option<&'a PatternDatabase>,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(puzzle: &Puzzle) -> Self {
+        let mut goal_position = vec![0; puzzle.goal.len()];
+        for (i, &tile) in puzzle.goal.iter().enumerate() {
+            goal_position[tile] = i;
+        }
+        Solver { width: puzzle.width, height: puzzle.height, goal_position, pdb: None }
+    }
+
+    // Also uses the pattern database. Fails unless it was built for the same board size and goal.
+    pub fn with_pattern_database(self, pdb: &'a PatternDatabase) -> anyhow::Result<Self> {
+        let same_goal = pdb.goal.len() == self.goal_position.len()
+            && pdb.goal.iter().enumerate().all(|(i, &tile)| self.goal_position[tile] == i);
+        if pdb.width != self.width || pdb.height != self.height || !same_goal {
+            anyhow::bail!("The pattern database was built for another board size or goal");
+        }
+        Ok(Solver { pdb: Some(pdb), ..self })
+    }
+
+    // Sum over the tiles (not the blank) of their Manhattan distance to their goal position
+    pub fn manhattan(&self, board: &[usize]) -> usize {
+        board.iter().enumerate()
+            .filter(|(_, &tile)| tile != 0)
+            .map(|(i, &tile)| {
+                let goal = self.goal_position[tile];
+                (i % self.width).abs_diff(goal % self.width) + (i / self.width).abs_diff(goal / self.width)
+            })
+            .sum()
+    }
+
+    // Extra moves needed by tiles that are in their goal row (or column) but in the wrong order: in each line,
+    // all the tiles but a longest correctly ordered subsequence have to leave the line and come back (2 moves each)
+    pub fn linear_conflicts(&self, board: &[usize]) -> usize {
+        let mut conflicts = 0;
+        for y in 0..self.height {
+            let goal_columns: Vec<usize> = (0..self.width)
+                .map(|x| board[y * self.width + x])
+                .filter(|&tile| tile != 0 && self.goal_position[tile] / self.width == y)
+                .map(|tile| self.goal_position[tile] % self.width)
+                .collect();
+            conflicts += goal_columns.len() - longest_increasing_subsequence(&goal_columns);
+        }
+        for x in 0..self.width {
+            let goal_rows: Vec<usize> = (0..self.height)
+                .map(|y| board[y * self.width + x])
+                .filter(|&tile| tile != 0 && self.goal_position[tile] % self.width == x)
+                .map(|tile| self.goal_position[tile] / self.width)
+                .collect();
+            conflicts += goal_rows.len() - longest_increasing_subsequence(&goal_rows);
+        }
+        2 * conflicts
+    }
+
+    // Admissible estimate of the number of moves to the goal: Manhattan distance plus linear conflicts,
+    // or the pattern database heuristic if it is larger
+    pub fn heuristic(&self, board: &[usize]) -> usize {
+        let h = self.manhattan(board) + self.linear_conflicts(board);
+        self.pdb.map_or(h, |pdb| h.max(pdb.heuristic_unchecked(board)))
+    }
+
+    // Returns a shortest sequence of actions (moves of the blank, as in Puzzle::step) from the board to the goal,
+    // or None if the goal can not be reached within max_length moves
+    pub fn solve(&self, board: &[usize], max_length: usize) -> Option<Vec<usize>> {
+        let mut board = board.to_vec();
+        let zero = board.iter().position(|&tile| tile == 0)?;
+        let mut path = vec![];
+        let mut bound = self.heuristic(&board);
+        while bound <= max_length {
+            match self.search(&mut board, zero, 0, bound, &mut path) {
+                None => return Some(path),
+                Some(next_bound) => bound = next_bound,
+            }
+        }
+        None
+    }
+
+    // Depth-first search with cost bound. Returns None if the goal was found (the path then leads to it),
+    // otherwise the smallest cost above the bound that was cut off.
+    fn search(&self, board: &mut Vec<usize>, zero: usize, cost: usize, bound: usize, path: &mut Vec<usize>) -> Option<usize> {
+        let h = self.heuristic(board);
+        if cost + h > bound {
+            return Some(cost + h);
+        }
+        if h == 0 {
+            return None;
+        }
+        let mut next_bound = usize::MAX;
+        for action in 0..4 {
+            // Never undo the previous move
+            if path.last() == Some(&((action + 2) % 4)) {
+                continue;
+            }
+            let Some(next_zero) = self.move_blank(zero, action) else { continue };
+            board.swap(zero, next_zero);
+            path.push(action);
+            match self.search(board, next_zero, cost + 1, bound, path) {
+                None => return None,
+                Some(b) => next_bound = next_bound.min(b),
+            }
+            path.pop();
+            board.swap(zero, next_zero);
+        }
+        Some(next_bound)
+    }
+
+    // Index of the blank after the action (left, up, right, down), if the move is allowed
+    fn move_blank(&self, zero: usize, action: usize) -> Option<usize> {
+        let (x, y) = (zero % self.width, zero / self.width);
+        match action {
+            0 if x > 0 => Some(zero - 1),
+            1 if y > 0 => Some(zero - self.width),
+            2 if x < self.width - 1 => Some(zero + 1),
+            3 if y < self.height - 1 => Some(zero + self.width),
+            _ => None,
+        }
+    }
+}
+
+fn longest_increasing_subsequence(values: &[usize]) -> usize {
+    // tails[k] is the smallest tail of an increasing subsequence of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    for &v in values {
+        match tails.binary_search(&v) {
+            Ok(_) => {}
+            Err(k) if k == tails.len() => tails.push(v),
+            Err(k) => tails[k] = v,
+        }
+    }
+    tails.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rl::env::Env;
+
+    // Plays the actions and checks that they reach the goal
+    fn reaches_goal(puzzle: &Puzzle, actions: &[usize]) -> bool {
+        let mut puzzle = puzzle.clone();
+        for &action in actions {
+            if !puzzle.masks()[action] {
+                return false;
+            }
+            puzzle.step(action);
+        }
+        puzzle.solved()
+    }
+
+    #[test]
+    fn test_heuristics() {
+        let puzzle = Puzzle::new(3, 3, 0, 1, 10);
+        let solver = Solver::new(&puzzle);
+        assert_eq!(solver.heuristic(&puzzle.goal), 0);
+        // 2 and 1 are swapped in their goal row: 2 moves each for Manhattan, and one tile has to leave the row
+        assert_eq!(solver.manhattan(&[0, 2, 1, 3, 4, 5, 6, 7, 8]), 2);
+        assert_eq!(solver.linear_conflicts(&[0, 2, 1, 3, 4, 5, 6, 7, 8]), 2);
+        assert_eq!(longest_increasing_subsequence(&[2, 0, 1, 3]), 3);
+    }
+
+    #[test]
+    fn test_solve_optimal() {
+        // 8-puzzle at an exact distance, checked against BFS
+        let mut puzzle = Puzzle::new(3, 3, 12, 1, 20);
+        puzzle.reset_mode = crate::envs::puzzle::ResetMode::ExactDistance;
+        puzzle.set_seed(0);
+        for _ in 0..3 {
+            puzzle.reset();
+            let solution = Solver::new(&puzzle).solve(&puzzle.state, 31).unwrap();
+            assert_eq!(solution.len(), 12);
+            assert!(reaches_goal(&puzzle, &solution));
+        }
+
+        // 15-puzzle with a non-identity goal
+        let mut puzzle = Puzzle::new(4, 4, 40, 1, 80);
+        puzzle.set_goal((0..16).rev().collect()).unwrap();
+        puzzle.set_seed(1);
+        puzzle.reset();
+        let solution = puzzle.optimal_solution().unwrap();
+        assert!(solution.len() <= 40);
+        assert!(reaches_goal(&puzzle, &solution));
+
+        // Same length with a pattern database
+        let pdb = PatternDatabase::build(&puzzle, (1..16).collect::<Vec<usize>>().chunks(3).map(|c| c.to_vec()).collect()).unwrap();
+        let pdb_solution = puzzle.optimal_solution_with(&pdb).unwrap().unwrap();
+        assert_eq!(pdb_solution.len(), solution.len());
+        assert!(reaches_goal(&puzzle, &pdb_solution));
+
+        // The database of another goal or board size is rejected
+        assert!(Solver::new(&Puzzle::new(4, 4, 0, 1, 10)).with_pattern_database(&pdb).is_err());
+        assert!(Solver::new(&Puzzle::new(3, 3, 0, 1, 10)).with_pattern_database(&pdb).is_err());
+        assert!(Puzzle::new(4, 4, 0, 1, 10).optimal_solution_with(&pdb).is_err());
+
+        // Unsolvable boards are not solved
+        let solver = Solver::new(&Puzzle::new(2, 2, 0, 1, 10));
+        assert_eq!(solver.solve(&[0, 2, 1, 3], 20), None);
+        assert_eq!(solver.solve(&[0, 1, 2, 3], 20), Some(vec![]));
+    }
+}
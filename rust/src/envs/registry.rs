@@ -0,0 +1,102 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+
+use crate::rl::env::Env;
+use crate::envs::permutation::PermutationPuzzle;
+use crate::envs::puzzle::Puzzle;
+
+// Builds an env from its JSON config
+pub type EnvConstructor = fn(&Value) -> Result<Box<dyn Env>>;
+
+// Registry of the envs that can be built by name, starting with the envs of this crate
+fn registry() -> &'static RwLock<HashMap<String, EnvConstructor>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, EnvConstructor>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut envs: HashMap<String, EnvConstructor> = HashMap::new();
+        envs.insert("Puzzle".into(), |config| Ok(Box::new(Puzzle::from_config(config)?)));
+        envs.insert("PermutationPuzzle".into(), |config| Ok(Box::new(PermutationPuzzle::from_config(config)?)));
+        RwLock::new(envs)
+    })
+}
+
+// Registers an env under the given name. Fails if the name is already taken.
+pub fn register_env(name: &str, constructor: EnvConstructor) -> Result<()> {
+    let mut envs = registry().write().map_err(|_| anyhow!("Env registry is poisoned"))?;
+    if envs.contains_key(name) {
+        bail!("Env `{}` is already registered", name);
+    }
+    envs.insert(name.into(), constructor);
+    Ok(())
+}
+
+// Returns the names of all the registered envs, sorted
+pub fn registered_envs() -> Vec<String> {
+    let envs = registry().read().expect("Env registry is poisoned");
+    let mut names: Vec<String> = envs.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+// Builds the env registered under the given name from its config
+pub fn make_env(name: &str, config: &Value) -> Result<Box<dyn Env>> {
+    let constructor = *registry().read().map_err(|_| anyhow!("Env registry is poisoned"))?
+        .get(name)
+        .ok_or_else(|| anyhow!("Unknown env `{}`, registered envs are: {}", name, registered_envs().join(", ")))?;
+    constructor(config).map_err(|err| anyhow!("Invalid config for env `{}`: {}", name, err))
+}
+
+// Same as make_env, with the config given as a JSON string
+pub fn make_env_from_json(name: &str, config: &str) -> Result<Box<dyn Env>> {
+    let config = serde_json::from_str(config).map_err(|err| anyhow!("Invalid JSON config for env `{}`: {}", name, err))?;
+    make_env(name, &config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_make_registered_env() {
+        let config = json!({"width": 3, "height": 2, "difficulty": 1, "depth_slope": 2, "max_depth": 16});
+        let env = make_env("Puzzle", &config).unwrap();
+        assert_eq!(env.num_actions(), 4);
+        assert_eq!(env.obs_shape(), vec![6, 6]);
+
+        assert!(make_env("Puzzle", &json!({"width": 3})).is_err());
+
+        let config = json!({"width": 2, "height": 2, "difficulty": 1, "depth_slope": 2, "max_depth": 16, "goal": [3, 2, 1, 0], "goal_in_obs": true});
+        let env = make_env("Puzzle", &config).unwrap();
+        assert_eq!(env.get_goal(), Some(vec![3, 2, 1, 0]));
+        assert_eq!(env.obs_shape(), vec![8, 4]);
+        assert!(make_env("NoSuchEnv", &config).is_err());
+
+        let config = json!({"generators": [[1, 2, 0], [2, 0, 1]], "difficulty": 1, "depth_slope": 2, "max_depth": 16});
+        let env = make_env("PermutationPuzzle", &config).unwrap();
+        assert_eq!(env.num_actions(), 2);
+        assert_eq!(env.obs_shape(), vec![3, 3]);
+    }
+
+    #[test]
+    fn test_register_env() {
+        register_env("TestPuzzle2x2", |_| Ok(Box::new(Puzzle::new(2, 2, 1, 1, 4)))).unwrap();
+        assert!(register_env("TestPuzzle2x2", |_| bail!("unused")).is_err());
+        assert!(registered_envs().contains(&"TestPuzzle2x2".to_string()));
+        assert_eq!(make_env_from_json("TestPuzzle2x2", "{}").unwrap().obs_shape(), vec![4, 4]);
+    }
+}
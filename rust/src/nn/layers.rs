@@ -13,6 +13,8 @@ that they have been altered from the originals.
 
 use nalgebra::{DMatrix, DVector};
 
+use crate::rl::env::Observation;
+
 #[derive(Clone)]
 pub struct Linear {
     weights: DMatrix<f32>,
@@ -35,6 +37,10 @@ impl Linear {
         }
         out
     }
+
+    pub fn output_size(&self) -> usize {
+        self.bias.len()
+    }
 }
 
 #[derive(Clone)]
@@ -48,41 +54,64 @@ pub struct EmbeddingBag {
 
 impl EmbeddingBag {
     pub fn new(vec_vectors: Vec<Vec<f32>>, bias_vector: Vec<f32>, apply_relu: bool, obs_shape: Vec<usize>, conv_dim: usize) -> Self {
-        let vectors = vec_vectors.into_iter().map(|vec| DVector::from_vec(vec)).collect();
+        let vectors = vec_vectors.into_iter().map(DVector::from_vec).collect();
         let bias = DVector::from_vec(bias_vector);
         Self { vectors, bias, apply_relu, obs_shape, conv_dim }
     }
 
-    pub fn forward(&self, input: &Vec<usize>) -> DVector<f32> {
+    pub fn output_size(&self) -> usize {
+        self.bias.len()
+    }
+
+    pub fn forward(&self, input: &[usize]) -> DVector<f32> {
+        let mut out = self.bias.clone();
+        for &i in input.iter() {
+            self.add_embedding(&mut out, i, 1.0);
+        }
+        self.activation(out)
+    }
+
+    // Same as forward, but each embedding is scaled by the value of its feature
+    pub fn forward_weighted(&self, input: &[(usize, f32)]) -> DVector<f32> {
         let mut out = self.bias.clone();
+        for &(i, w) in input.iter() {
+            self.add_embedding(&mut out, i, w);
+        }
+        self.activation(out)
+    }
+
+    pub fn forward_observation(&self, input: &Observation) -> DVector<f32> {
+        match input {
+            Observation::Sparse(indices) => self.forward(indices),
+            _ => self.forward_weighted(&input.to_weighted()),
+        }
+    }
+
+    fn add_embedding(&self, out: &mut DVector<f32>, i: usize, w: f32) {
         if self.obs_shape.len() == 1 {
             // This is standard embeddings / linear
-            for &i in input.iter() {
-                out += &self.vectors[i];
-            }
+            out.axpy(w, &self.vectors[i], 1.0);
         } else if self.obs_shape.len() == 2 {
             let v_size = self.vectors[0].len();
             // This is conv1d
-            for &i in input.iter() {
-                // obs_shape[0] is the size of each col (i.e. the number of rows)
-                let mut row = i / self.obs_shape[1]; 
-                let mut col = i % self.obs_shape[1];
-
-                // If conv_dim is 1 then we swap row and col
-                if self.conv_dim == 1 {(row, col) = (col, row);}
+            // obs_shape[0] is the size of each col (i.e. the number of rows)
+            let mut row = i / self.obs_shape[1]; 
+            let mut col = i % self.obs_shape[1];
 
-                let mut out_slice = out.rows_mut(col * v_size, v_size);
-                out_slice += &self.vectors[row];
-
-            }
+            // If conv_dim is 1 then we swap row and col
+            if self.conv_dim == 1 {(row, col) = (col, row);}
 
+            let mut out_slice = out.rows_mut(col * v_size, v_size);
+            out_slice.axpy(w, &self.vectors[row], 1.0);
         } // TODO: add conv2d
+    }
 
-
+    fn activation(&self, out: DVector<f32>) -> DVector<f32> {
         if self.apply_relu {
-            out =  out.map(relu);
+            out.map(relu)
+        } else {
+            out
         }
-        out
     }
 }
 
@@ -119,7 +148,22 @@ mod tests {
             vec![2],
             0,
         );
-        let out = emb.forward(&vec![0, 1]);
+        let out = emb.forward(&[0, 1]);
         assert_eq!(out, DVector::from_vec(vec![4.0, 6.0]));
     }
+
+    #[test]
+    fn test_embedding_bag_forward_observation() {
+        let emb = EmbeddingBag::new(
+            vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+            vec![0.0, 0.0],
+            false,
+            vec![2],
+            0,
+        );
+        let expected = DVector::from_vec(vec![3.5, 5.0]);
+        assert_eq!(emb.forward_observation(&Observation::Weighted(vec![(0, 0.5), (1, 1.0)])), expected);
+        assert_eq!(emb.forward_observation(&Observation::Dense(vec![0.5, 1.0])), expected);
+        assert_eq!(emb.forward_observation(&Observation::Sparse(vec![0, 1])), emb.forward(&[0, 1]));
+    }
 }
\ No newline at end of file
@@ -17,14 +17,22 @@ use crate::nn::layers::Linear;
 
 #[derive(Clone)]
 pub struct Sequential {
+    // Boxed like the layers of PyLinear, which the Python bindings clone into here
+    #[allow(clippy::vec_box)]
     layers: Vec<Box<Linear>>,
 }
 
 impl Sequential {
+    #[allow(clippy::vec_box)]
     pub fn new(layers: Vec<Box<Linear>>) -> Self {
         Self { layers }
     }
 
+    // Size of the output for inputs of the given size (an empty Sequential is the identity)
+    pub fn output_size(&self, input_size: usize) -> usize {
+        self.layers.last().map_or(input_size, |layer| layer.output_size())
+    }
+
     pub fn forward(&self, input: DVector<f32>) -> DVector<f32> {
         let mut x = input;
         for layer in &self.layers {
@@ -13,8 +13,10 @@ that they have been altered from the originals.
 
 use rand::{prelude::Distribution, Rng};
 
+use anyhow::{bail, Result};
 use crate::nn::modules::Sequential;
 use crate::nn::layers::EmbeddingBag;
+use crate::rl::env::{Env, EnvResult, Observation, encode_action};
 
 #[derive(Clone)]
 pub struct Policy {
@@ -23,35 +25,85 @@ pub struct Policy {
     action_net: Box<Sequential>,
     value_net: Box<Sequential>,
     obs_perms: Vec<Vec<usize>>,
-    act_perms: Vec<Vec<usize>>
+    act_perms: Vec<Vec<usize>>,
+    // Sizes of the components of a factored action space (empty for flat action spaces)
+    action_dims: Vec<usize>,
 }
 
 impl Policy {
     pub fn new(embeddings: Box<EmbeddingBag>, common: Box<Sequential>, action_net: Box<Sequential>, value_net: Box<Sequential>, obs_perms: Vec<Vec<usize>>, act_perms: Vec<Vec<usize>>) -> Self {
-        Self { embeddings: embeddings, common, action_net, value_net, obs_perms, act_perms }
+        Self { embeddings, common, action_net, value_net, obs_perms, act_perms, action_dims: vec![] }
     }
 
-    pub fn predict(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
-        // Forward of the action net
-        let (action_logits, value) = self._raw_predict(obs, self.get_perm_id());
+    // Makes the policy factored: the action net outputs one head of logits per action component, concatenated.
+    // Twists permute flat actions, so factored policies can not have any.
+    pub fn with_action_dims(mut self, action_dims: Vec<usize>) -> Result<Self> {
+        if action_dims.len() > 1 {
+            if !self.act_perms.is_empty() {
+                bail!("factored policies do not support twists, got {} action permutations", self.act_perms.len());
+            }
+            let num_logits = self.action_net.output_size(self.common.output_size(self.embeddings.output_size()));
+            if action_dims.iter().sum::<usize>() != num_logits {
+                bail!("the action net outputs {} logits, but the action dims {:?} need one per component value", num_logits, action_dims);
+            }
+        }
+        self.action_dims = action_dims;
+        Ok(self)
+    }
 
-        // Apply masks to the actions
-        let mut exp_masked_probs: Vec<f32> = action_logits.iter().zip(masks.iter()).map(|(&a, &m)| if m {a.exp()} else {0.0}).collect();
+    pub fn action_dims(&self) -> &[usize] {
+        &self.action_dims
+    }
+
+    pub fn is_factored(&self) -> bool {
+        self.action_dims.len() > 1
+    }
+
+    // Samples an action for the current state of the env, one component at a time for factored policies.
+    // Returns the masked logits (the concatenated heads for factored policies), the value and the flat action.
+    pub fn forward_env(&self, env: &dyn Env, rng: &mut impl Rng) -> EnvResult<(Vec<f32>, f32, usize)> {
+        if !self.is_factored() {
+            let (logits, value) = self.forward(env.try_observation()?, env.try_masks()?, rng);
+            let action = sample_from_logits(&logits, rng);
+            return Ok((logits, value, action));
+        }
+        let (action_logits, value) = self._raw_predict(env.try_observation()?, self.get_perm_id(rng));
+        let (masked_logits, components) = sample_from_factored_logits(&action_logits, &self.action_dims, |prefix| env.try_component_masks(prefix), rng)?;
+        Ok((masked_logits, value, encode_action(&self.action_dims, &components)))
+    }
+
+    // Same as predict, reading the observation and masks from the env. Factored policies give the probabilities of the flat actions.
+    pub fn predict_env(&self, env: &dyn Env, rng: &mut impl Rng) -> EnvResult<(Vec<f32>, f32)> {
+        if !self.is_factored() {
+            return Ok(self.predict(env.try_observation()?, env.try_masks()?, rng));
+        }
+        let (action_logits, value) = self._raw_predict(env.try_observation()?, self.get_perm_id(rng));
+        Ok((factored_probs(&action_logits, &self.action_dims, |prefix| env.try_component_masks(prefix))?, value))
+    }
 
-        // TODO: apply noise to the actions
+    // Same as full_predict, reading the observation and masks from the env. Factored policies give the probabilities of the flat actions.
+    pub fn full_predict_env(&self, env: &dyn Env) -> EnvResult<(Vec<f32>, f32)> {
+        if !self.is_factored() {
+            return Ok(self.full_predict(env.try_observation()?, env.try_masks()?));
+        }
+        let (action_logits, value) = self._averaged_raw_predict(env.try_observation()?);
+        Ok((factored_probs(&action_logits, &self.action_dims, |prefix| env.try_component_masks(prefix))?, value))
+    }
 
-        // Normalize actions
-        let action_probs_sum: f32 = exp_masked_probs.iter().sum();
-        exp_masked_probs = exp_masked_probs.iter().map(|&v| v / (action_probs_sum + 0.000001)).collect();
-        (exp_masked_probs, value)
+    pub fn predict(&self, obs: impl Into<Observation>, masks: Vec<bool>, rng: &mut impl Rng) -> (Vec<f32>, f32) {
+        // Forward of the action net
+        let (action_logits, value) = self._raw_predict(obs.into(), self.get_perm_id(rng));
+
+        // Apply masks to the actions and normalize
+        (masked_softmax(&action_logits, &masks), value)
     }
 
 
-    pub fn forward(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
+    pub fn forward(&self, obs: impl Into<Observation>, masks: Vec<bool>, rng: &mut impl Rng) -> (Vec<f32>, f32) {
         // Similar to predict but outputs unnormalized logits instead of probabilities
 
         // Forward of the action net
-        let (action_logits, value) = self._raw_predict(obs, self.get_perm_id());
+        let (action_logits, value) = self._raw_predict(obs.into(), self.get_perm_id(rng));
 
         // Apply masks to the actions
         let masked_logits: Vec<f32> = action_logits.iter().zip(masks.iter()).map(|(&a, &m)| if m {a} else {-1e10}).collect();
@@ -59,26 +111,25 @@ impl Policy {
         (masked_logits, value)
     }
 
-    fn get_perm_id(&self) -> Option<usize> {
+    fn get_perm_id(&self, rng: &mut impl Rng) -> Option<usize> {
         let mut n_perm: Option<usize> = None;
 
         // Select a random permutation (if there are perms)
-        if self.obs_perms.len() > 0 {
-            let mut rng = rand::thread_rng();
-            n_perm = Some(rand::distributions::Uniform::new(0, self.obs_perms.len()).sample(&mut rng));
+        if !self.obs_perms.is_empty() {
+            n_perm = Some(rand::distributions::Uniform::new(0, self.obs_perms.len()).sample(rng));
         }
 
         n_perm
     }
 
-    fn _raw_predict(&self, mut obs: Vec<usize>, n_perm: Option<usize>) -> (Vec<f32>, f32) {
+    fn _raw_predict(&self, mut obs: Observation, n_perm: Option<usize>) -> (Vec<f32>, f32) {
         // Permute the obs according to the obs_perm
         if let Some(pi) = n_perm {
-            obs = obs.iter().map(|&v| self.obs_perms[pi][v]).collect();
+            obs = obs.permute(&self.obs_perms[pi]);
         }
 
         // Do forward pass of the shared nn part
-        let common_out = self.common.forward(self.embeddings.forward(&obs));
+        let common_out = self.common.forward(self.embeddings.forward_observation(&obs));
 
         // Forward of the value net
         let value = self.value_net.forward(common_out.clone()).sum(); // This only has one element
@@ -94,8 +145,17 @@ impl Policy {
         (action_logits, value)
     }
 
-    pub fn full_predict(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
-        if self.obs_perms.len() == 0 {return self.predict(obs, masks);};
+    pub fn full_predict(&self, obs: impl Into<Observation>, masks: Vec<bool>) -> (Vec<f32>, f32) {
+        let (action_logits, value) = self._averaged_raw_predict(obs.into());
+        // Apply masks to the actions and normalize
+        (masked_softmax(&action_logits, &masks), value)
+    }
+
+    // Raw logits and value averaged over all the perms
+    fn _averaged_raw_predict(&self, obs: Observation) -> (Vec<f32>, f32) {
+        if self.obs_perms.is_empty() {
+            return self._raw_predict(obs, None);
+        };
 
         // Forward of the action net for each perm
         let mut action_logits = vec![0.0f32; self.act_perms[0].len()];
@@ -108,21 +168,23 @@ impl Policy {
                 action_logits[i] += action_logits_pi[i] / (self.obs_perms.len() as f32);
             }
         }
+        (action_logits, value)
+    }
 
-        // Apply masks to the actions
-        let mut exp_masked_probs: Vec<f32> = action_logits.iter().zip(masks.iter()).map(|(&a, &m)| if m {a.exp()} else {0.0}).collect();
+}
 
-        // TODO: apply noise to the actions
+fn masked_softmax(action_logits: &[f32], masks: &[bool]) -> Vec<f32> {
+    // Apply masks to the actions
+    let exp_masked_probs: Vec<f32> = action_logits.iter().zip(masks.iter()).map(|(&a, &m)| if m {a.exp()} else {0.0}).collect();
 
-        // Normalize actions
-        let action_probs_sum: f32 = exp_masked_probs.iter().sum();
-        exp_masked_probs = exp_masked_probs.iter().map(|&v| v / (action_probs_sum + 0.000001)).collect();
-        (exp_masked_probs, value)
-    }
+    // TODO: apply noise to the actions
 
+    // Normalize actions
+    let action_probs_sum: f32 = exp_masked_probs.iter().sum();
+    exp_masked_probs.iter().map(|&v| v / (action_probs_sum + 0.000001)).collect()
 }
 
-pub fn argmax(values: &Vec<f32>) -> usize {
+pub fn argmax(values: &[f32]) -> usize {
     // If the vector is empty, return 0 by default.
     if values.is_empty() {
         return 0;
@@ -145,12 +207,10 @@ pub fn argmax(values: &Vec<f32>) -> usize {
     max_idx
 }
 
-pub fn sample(probs: &Vec<f32>) -> usize {
-    let mut rng = rand::thread_rng();  // Random number generator
-
+pub fn sample(probs: &[f32], rng: &mut impl Rng) -> usize {
     match rand::distributions::WeightedIndex::new(probs) {
         Ok(dist) => {
-            dist.sample(&mut rng)
+            dist.sample(rng)
         }
         Err(err) => {
             // Handle the error and print the `probs` value
@@ -161,14 +221,73 @@ pub fn sample(probs: &Vec<f32>) -> usize {
     }
 }
 
-pub fn sample_from_logits(probs: &Vec<f32>) -> usize {
-    let mut rng = rand::thread_rng();  // Random number generator
-    argmax(&probs.iter().map(|&v| v - rng.gen::<f32>().ln().abs().ln()).collect())
+pub fn sample_from_logits(probs: &[f32], rng: &mut impl Rng) -> usize {
+    argmax(&probs.iter().map(|&v| v - rng.gen::<f32>().ln().abs().ln()).collect::<Vec<f32>>())
+}
+
+// Splits the concatenated logits of a factored policy into one head per action component
+pub fn split_heads<'a>(logits: &'a [f32], action_dims: &[usize]) -> Vec<&'a [f32]> {
+    let mut heads = Vec::with_capacity(action_dims.len());
+    let mut rest = logits;
+    for &dim in action_dims {
+        let (head, tail) = rest.split_at(dim);
+        heads.push(head);
+        rest = tail;
+    }
+    heads
+}
+
+// Samples the components of a factored action one after the other from the concatenated head logits.
+// masks(prefix) returns the mask of the next component given the components already sampled.
+// Returns the masked logits and the components.
+pub fn sample_from_factored_logits<E>(
+    logits: &[f32],
+    action_dims: &[usize],
+    mut masks: impl FnMut(&[usize]) -> Result<Vec<bool>, E>,
+    rng: &mut impl Rng,
+) -> Result<(Vec<f32>, Vec<usize>), E> {
+    let mut masked_logits = Vec::with_capacity(logits.len());
+    let mut components = Vec::with_capacity(action_dims.len());
+    for head in split_heads(logits, action_dims) {
+        let head_masks = masks(&components)?;
+        let masked_head: Vec<f32> = head.iter().zip(head_masks.iter()).map(|(&a, &m)| if m {a} else {-1e10}).collect();
+        components.push(sample_from_logits(&masked_head, rng));
+        masked_logits.extend(masked_head);
+    }
+    Ok((masked_logits, components))
+}
+
+// Probabilities of all the flat actions of a factored policy: products of the conditional probabilities of their components
+fn factored_probs<E>(
+    logits: &[f32],
+    action_dims: &[usize],
+    mut masks: impl FnMut(&[usize]) -> Result<Vec<bool>, E>,
+) -> Result<Vec<f32>, E> {
+    let heads = split_heads(logits, action_dims);
+    let mut probs = vec![0.0; action_dims.iter().product()];
+    // Depth-first over the prefixes with a non-zero probability
+    let mut stack = vec![(vec![], 1.0f32)];
+    while let Some((prefix, prob)) = stack.pop() {
+        if prefix.len() == action_dims.len() {
+            probs[encode_action(action_dims, &prefix)] = prob;
+            continue;
+        }
+        let head_probs = masked_softmax(heads[prefix.len()], &masks(&prefix)?);
+        for (c, &p) in head_probs.iter().enumerate().filter(|(_, &p)| p > 0.0) {
+            let mut next = prefix.clone();
+            next.push(c);
+            stack.push((next, prob * p));
+        }
+    }
+    Ok(probs)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rl::rng::seeded_rng;
+    use crate::nn::layers::Linear;
+    use crate::rl::env::decode_action;
 
     #[test]
     fn test_argmax_basic() {
@@ -185,14 +304,105 @@ mod tests {
     #[test]
     fn test_sample_range() {
         let probs = vec![0.2, 0.3, 0.5];
-        let idx = sample(&probs);
+        let idx = sample(&probs, &mut rand::thread_rng());
         assert!(idx < probs.len());
     }
 
     #[test]
     fn test_sample_from_logits_range() {
         let logits = vec![0.1, 2.0, 0.3];
-        let idx = sample_from_logits(&logits);
+        let idx = sample_from_logits(&logits, &mut rand::thread_rng());
         assert!(idx < logits.len());
     }
+
+    #[test]
+    fn test_full_predict_observation_formats() {
+        let emb = EmbeddingBag::new(vec![vec![1.0], vec![-2.0], vec![0.5]], vec![0.0], false, vec![3], 0);
+        let lin = Linear::new(vec![1.0, -1.0], vec![0.0, 0.0], false);
+        let policy = Policy::new(
+            Box::new(emb),
+            Box::new(Sequential::new(vec![])),
+            Box::new(Sequential::new(vec![Box::new(lin)])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))])),
+            vec![],
+            vec![],
+        );
+        let masks = vec![true, true];
+        let sparse = policy.full_predict(vec![0, 2], masks.clone());
+        assert_eq!(policy.full_predict(vec![(0, 1.0), (2, 1.0)], masks.clone()), sparse);
+        assert_eq!(policy.full_predict(vec![1.0, 0.0, 1.0], masks), sparse);
+    }
+
+    #[test]
+    fn test_sample_seeded() {
+        let logits = vec![0.1, 2.0, 0.3, 1.5];
+        let draws = |seed| {
+            let mut rng = seeded_rng(seed);
+            (0..32).map(|_| sample_from_logits(&logits, &mut rng)).collect::<Vec<usize>>()
+        };
+        assert_eq!(draws(3), draws(3));
+    }
+
+    // Actions (a, b) with a in 0..2 and b in 0..3, of which only (0, 2), (1, 1) and (1, 2) are allowed
+    #[derive(Clone)]
+    struct FactoredEnv;
+
+    impl Env for FactoredEnv {
+        fn as_any(&self) -> &dyn std::any::Any { self }
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
+        fn num_actions(&self) -> usize { 6 }
+        fn action_dims(&self) -> Vec<usize> { vec![2, 3] }
+        fn obs_shape(&self) -> Vec<usize> { vec![1] }
+        fn set_state(&mut self, _state: Vec<i64>) {}
+        fn get_state(&self) -> Vec<i64> { vec![] }
+        fn reset(&mut self) {}
+        fn step(&mut self, _action: usize) {}
+        fn masks(&self) -> Vec<bool> { vec![false, false, true, false, true, true] }
+        fn is_final(&self) -> bool { false }
+        fn reward(&self) -> f32 { 0.0 }
+        fn observe(&self) -> Vec<usize> { vec![0] }
+    }
+
+    #[test]
+    fn test_factored_policy() {
+        let env = FactoredEnv;
+        assert_eq!(decode_action(&[2, 3], 5), vec![1, 2]);
+        assert_eq!(encode_action(&[2, 3], &[1, 2]), 5);
+        assert_eq!(env.component_masks(&[]), vec![true, true]);
+        assert_eq!(env.component_masks(&[1]), vec![false, true, true]);
+
+        let policy = Policy::new(
+            Box::new(EmbeddingBag::new(vec![vec![1.0]], vec![0.0], false, vec![1], 0)),
+            Box::new(Sequential::new(vec![])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![0.5, -0.5, 1.0, 2.0, 0.3], vec![0.0; 5], false))])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))])),
+            vec![],
+            vec![],
+        ).with_action_dims(vec![2, 3]).unwrap();
+
+        let mut rng = seeded_rng(0);
+        for _ in 0..20 {
+            let (logits, _, action) = policy.forward_env(&env, &mut rng).unwrap();
+            assert_eq!(logits.len(), 5);
+            assert!(env.masks()[action]);
+        }
+
+        let (probs, _) = policy.predict_env(&env, &mut rng).unwrap();
+        assert!((probs.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        assert!(probs.iter().zip(env.masks()).all(|(&p, m)| m || p == 0.0));
+        assert_eq!(policy.full_predict_env(&env).unwrap(), (probs, 1.0));
+
+        // The heads must have one logit per component value, and twists of the flat actions do not apply
+        let make_policy = |num_logits: usize, act_perms: Vec<Vec<usize>>| Policy::new(
+            Box::new(EmbeddingBag::new(vec![vec![1.0]], vec![0.0], false, vec![1], 0)),
+            Box::new(Sequential::new(vec![])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![1.0; num_logits], vec![0.0; num_logits], false))])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))])),
+            act_perms.iter().map(|_| vec![0]).collect(),
+            act_perms,
+        );
+        assert!(make_policy(6, vec![]).with_action_dims(vec![2, 3]).is_err());
+        assert!(make_policy(5, vec![(0..6).collect()]).with_action_dims(vec![2, 3]).is_err());
+        assert!(make_policy(6, vec![(0..6).collect()]).with_action_dims(vec![]).is_ok());
+    }
 }
@@ -13,11 +13,14 @@ that they have been altered from the originals.
 
 use pyo3::prelude::*;
 use std::collections::HashMap;
+use anyhow::anyhow;
 
 use crate::collector::collector::{Collector, CollectedData};
 use crate::collector::ppo::PPOCollector;
 use crate::collector::az::AZCollector;
+use crate::collector::difficulty::DifficultyDistribution;
 use crate::python_interface::policy::PyPolicy;
+use crate::rl::env::Observation;
 use crate::python_interface::env::get_env;
 use crate::python_interface::error_mapping::MyError;
 
@@ -32,7 +35,7 @@ impl PyCollectedData {
     /// Construct a new PyCollectedData from raw rollout vectors.
     #[new]
     pub fn new(
-        obs: Vec<Vec<usize>>,
+        obs: Vec<Observation>,
         logits: Vec<Vec<f32>>,
         values: Vec<f32>,
         rewards: Vec<f32>,
@@ -51,12 +54,12 @@ impl PyCollectedData {
     // Getter and setter methods for the Python interface
     
     #[getter]
-    fn get_obs(&self) -> Vec<Vec<usize>> {
+    fn get_obs(&self) -> Vec<Observation> {
         self.inner.obs.clone()
     }
     
     #[setter]
-    fn set_obs(&mut self, obs: Vec<Vec<usize>>) {
+    fn set_obs(&mut self, obs: Vec<Observation>) {
         self.inner.obs = obs;
     }
     
@@ -121,6 +124,15 @@ impl PyCollectedData {
     }
 }
 
+// Reads a difficulty distribution given as a dict, e.g. {"type": "replay", "replay_prob": 0.2}. None means fixed.
+fn extract_difficulty_distribution(dist: Option<&Bound<'_, PyAny>>) -> PyResult<DifficultyDistribution> {
+    let Some(dist) = dist else {
+        return Ok(DifficultyDistribution::Fixed);
+    };
+    let json: String = dist.py().import("json")?.call_method1("dumps", (dist,))?.extract()?;
+    Ok(serde_json::from_str(&json).map_err(|err| MyError::from(anyhow!("Invalid difficulty distribution {}: {}", json, err)))?)
+}
+
 #[pyclass(subclass)]
 pub struct PyBaseCollector {
     collector: Box<dyn Collector>,
@@ -131,7 +143,7 @@ impl PyBaseCollector {
     // Collects Data
     fn collect(&self, py_env: &Bound<'_, PyAny>, policy: &PyPolicy) -> PyResult<PyCollectedData>{
         let env_ref = get_env(py_env)?;
-        let collected_data = self.collector.collect(env_ref, &*policy.policy).map_err(MyError::from)?;
+        let collected_data = self.collector.collect(env_ref, &policy.policy).map_err(MyError::from)?;
         Ok(PyCollectedData { inner: collected_data })
     }
 }
@@ -142,14 +154,18 @@ pub struct PyPPOCollector {}
 #[pymethods]
 impl PyPPOCollector {
     #[new]
+    #[pyo3(signature = (num_episodes, gamma, lambda, num_cores, seed=0, difficulty_distribution=None))]
     pub fn new(
         num_episodes: usize,
         gamma: f32,
         lambda: f32,
         num_cores: usize,
-    ) -> (Self, PyBaseCollector) {
-        let collector = Box::new(PPOCollector::new(num_episodes, gamma, lambda, num_cores));
-        (PyPPOCollector {}, PyBaseCollector { collector: collector })
+        seed: u64,
+        difficulty_distribution: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<(Self, PyBaseCollector)> {
+        let mut collector = Box::new(PPOCollector::new(num_episodes, gamma, lambda, num_cores, seed));
+        collector.difficulty_distribution = extract_difficulty_distribution(difficulty_distribution)?;
+        Ok((PyPPOCollector {}, PyBaseCollector { collector }))
         // (PyPPOCollector { collector: collector.clone() }, PyBaseCollector { collector: collector })
     }
 }
@@ -160,14 +176,19 @@ pub struct PyAZCollector {}
 #[pymethods]
 impl PyAZCollector {
     #[new]
+    #[pyo3(signature = (num_episodes, num_mcts_searches, C, max_expand_depth, num_cores, seed=0, difficulty_distribution=None))]
+    #[allow(non_snake_case)]
     pub fn new(
         num_episodes: usize,
         num_mcts_searches: usize,
         C: f32,
         max_expand_depth: usize,
         num_cores: usize,
-    ) -> (Self, PyBaseCollector) {
-        let collector = Box::new(AZCollector::new(num_episodes, num_mcts_searches, C, max_expand_depth, num_cores));
-        (PyAZCollector { }, PyBaseCollector { collector: collector })
+        seed: u64,
+        difficulty_distribution: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<(Self, PyBaseCollector)> {
+        let mut collector = Box::new(AZCollector::new(num_episodes, num_mcts_searches, C, max_expand_depth, num_cores, seed));
+        collector.difficulty_distribution = extract_difficulty_distribution(difficulty_distribution)?;
+        Ok((PyAZCollector { }, PyBaseCollector { collector }))
     }
 }
@@ -12,14 +12,65 @@ that they have been altered from the originals.
 */
 
 use pyo3::prelude::*;
-use crate::rl::env::Env;
+use crate::rl::env::{Env, Observation};
 use crate::envs::puzzle::Puzzle;
+use crate::envs::permutation::{PermutationPuzzle, PermutationPuzzleConfig, Symmetry};
+use crate::envs::puzzle::solver::Solver;
+use crate::envs::puzzle::pdb::PatternDatabase;
+use crate::envs::registry;
 use crate::python_interface::policy::PyPolicy;
 use crate::python_interface::error_mapping::MyError;
 use crate::rl::solve::solve;
 use crate::rl::evaluate::evaluate;
+use crate::rl::twists::check_twists;
+use crate::rl::env::testing::check_env;
+use crate::rl::env::strict::StrictEnv;
 use std::any::Any;
 
+// Observation and action permutations, see Env::twists
+type Twists = (Vec<Vec<usize>>, Vec<Vec<usize>>);
+
+// Observations are lists of ints (sparse), lists of (int, float) tuples (weighted) or lists of floats (dense).
+// A list of ints is always sparse, so dense observations with int values are tagged: ("dense", values).
+// The other kinds can be tagged the same way, as ("sparse", indices) and ("weighted", pairs).
+impl<'py> FromPyObject<'py> for Observation {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok((kind, values)) = ob.extract::<(String, Bound<'py, PyAny>)>() {
+            return match kind.as_str() {
+                "sparse" => values.extract::<Vec<usize>>().map(Observation::Sparse),
+                "weighted" => values.extract::<Vec<(usize, f32)>>().map(Observation::Weighted),
+                "dense" => values.extract::<Vec<f32>>().map(Observation::Dense),
+                _ => Err(pyo3::exceptions::PyValueError::new_err(
+                    format!("Unknown observation kind {:?}, expected \"sparse\", \"weighted\" or \"dense\"", kind)
+                )),
+            };
+        }
+        if let Ok(indices) = ob.extract::<Vec<usize>>() {
+            return Ok(Observation::Sparse(indices));
+        }
+        if let Ok(pairs) = ob.extract::<Vec<(usize, f32)>>() {
+            return Ok(Observation::Weighted(pairs));
+        }
+        ob.extract::<Vec<f32>>().map(Observation::Dense).map_err(|_| pyo3::exceptions::PyTypeError::new_err(
+            "Observation must be a list of ints, a list of (int, float) tuples, a list of floats or a (kind, values) tuple"
+        ))
+    }
+}
+
+impl<'py> IntoPyObject<'py> for Observation {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
+        match self {
+            Observation::Sparse(indices) => indices.into_pyobject(py),
+            Observation::Weighted(pairs) => pairs.into_pyobject(py),
+            Observation::Dense(values) => values.into_pyobject(py),
+        }
+    }
+}
+
 /// Generic helper functions for extracting concrete environment types from PyBaseEnv
 pub fn get_env_ref<T: Any>(base_env: &PyBaseEnv) -> PyResult<&T> {
     base_env.env.as_any().downcast_ref::<T>()
@@ -48,15 +99,65 @@ impl PyBaseEnv {
         Ok(self.env.num_actions())
     }
 
+    // Returns the sizes of the components of factored actions ([num_actions] for flat action spaces)
+    fn action_dims(&self) -> PyResult<Vec<usize>> {
+        Ok(self.env.try_action_dims().map_err(MyError::from)?)
+    }
+
+    // Returns the masks of the next action component, given the components already chosen
+    fn component_masks(&self, prefix: Vec<usize>) -> PyResult<Vec<bool>> {
+        Ok(self.env.try_component_masks(&prefix).map_err(MyError::from)?)
+    }
+
     // Returns the size of the observations 
     fn obs_shape(&self) -> PyResult<Vec<usize>>{
         Ok(self.env.obs_shape())
     }
 
+    // Returns a text rendering of the current state
+    #[pyo3(signature = (ansi=false))]
+    fn render(&self, ansi: bool) -> PyResult<String> {
+        let rendering = if ansi { self.env.try_render_ansi() } else { self.env.try_render() };
+        Ok(rendering.map_err(MyError::from)?)
+    }
+
+    // Prints the rendering of the current state (with Python's print, so it shows in notebooks)
+    #[pyo3(signature = (ansi=false))]
+    fn display(&self, py: Python<'_>, ansi: bool) -> PyResult<()> {
+        py.import("builtins")?.getattr("print")?.call1((self.render(ansi)?,))?;
+        Ok(())
+    }
+
+    // Returns a human readable name of the action
+    fn action_name(&self, action: usize) -> PyResult<String> {
+        Ok(self.env.try_action_name(action).map_err(MyError::from)?)
+    }
+
+    // Returns the names of all the actions
+    fn action_names(&self) -> PyResult<Vec<String>> {
+        Ok(self.env.try_action_names().map_err(MyError::from)?)
+    }
+
+    // Returns a human readable description of an observation feature
+    fn obs_feature_name(&self, index: usize) -> PyResult<String> {
+        Ok(self.env.try_obs_feature_name(index).map_err(MyError::from)?)
+    }
+
+    // Returns the number of players taking turns
+    fn num_players(&self) -> PyResult<usize> {
+        Ok(self.env.try_num_players().map_err(MyError::from)?)
+    }
+
+    // Returns the player to move
+    fn current_player(&self) -> PyResult<usize> {
+        Ok(self.env.try_current_player().map_err(MyError::from)?)
+    }
+
     // Sets the current difficulty
     #[setter]
     fn set_difficulty(&mut self, difficulty: usize) -> PyResult<()> {
-        Ok(self.env.set_difficulty(difficulty))
+        self.env.set_difficulty(difficulty);
+        Ok(())
     }
 
     // Returns current difficulty
@@ -67,44 +168,125 @@ impl PyBaseEnv {
 
     // Sets itself a given input state (constructed from a Vec<usize>)
     fn set_state(&mut self, state: Vec<i64>) -> PyResult<()> {
-        Ok(self.env.set_state(state))
+        Ok(self.env.try_set_state(state).map_err(MyError::from)?)
+    }
+
+    // Returns the current state in the format accepted by set_state
+    fn get_state(&self) -> PyResult<Vec<i64>> {
+        Ok(self.env.try_get_state().map_err(MyError::from)?)
+    }
+
+    // Sets the goal the env has to reach, for goal-conditioned envs
+    fn set_goal(&mut self, goal: Vec<i64>) -> PyResult<()> {
+        Ok(self.env.set_goal(goal).map_err(MyError::from)?)
+    }
+
+    // Returns the current goal, or None if the env is not goal-conditioned
+    fn get_goal(&self) -> PyResult<Option<Vec<i64>>> {
+        Ok(self.env.try_get_goal().map_err(MyError::from)?)
+    }
+
+    // Seeds the random number generator used by reset
+    fn set_seed(&mut self, seed: u64) -> PyResult<()> {
+        Ok(self.env.try_set_seed(seed).map_err(MyError::from)?)
     }
 
     // Sets itself to a random initial state
     fn reset(&mut self) -> PyResult<()>{
-        Ok(self.env.reset())
+        Ok(self.env.try_reset().map_err(MyError::from)?)
     }
 
     // Evolves the current state by an action
     fn step(&mut self, action: usize) -> PyResult<()>{
-        Ok(self.env.step(action))
+        Ok(self.env.try_step(action).map_err(MyError::from)?)
+    }
+
+    // Returns True if steps have random outcomes
+    fn is_stochastic(&self) -> PyResult<bool> {
+        Ok(self.env.try_is_stochastic().map_err(MyError::from)?)
+    }
+
+    // Returns True if the env can undo actions
+    fn supports_undo(&self) -> PyResult<bool> {
+        Ok(self.env.supports_undo())
+    }
+
+    // Reverts a previous step with the given action
+    fn undo(&mut self, action: usize) -> PyResult<()> {
+        Ok(self.env.undo(action).map_err(MyError::from)?)
     }
 
     // Returns an array with the action masks (True if an action is allowed, False if not)
     fn masks(&self) -> PyResult<Vec<bool>> {
-        Ok(self.env.masks())
+        Ok(self.env.try_masks().map_err(MyError::from)?)
     }
 
     // Returns True if the given state is a terminal state
     fn is_final(&self) -> PyResult<bool>{
-        Ok(self.env.is_final())
+        Ok(self.env.try_is_final().map_err(MyError::from)?)
+    }
+
+    // Returns True if the episode was cut short (e.g. by a step limit)
+    fn is_truncated(&self) -> PyResult<bool>{
+        Ok(self.env.try_is_truncated().map_err(MyError::from)?)
+    }
+
+    // Returns True if the episode ended in a genuinely terminal state
+    fn is_terminated(&self) -> PyResult<bool>{
+        let is_final = self.env.try_is_final().map_err(MyError::from)?;
+        Ok(is_final && !self.env.try_is_truncated().map_err(MyError::from)?)
     }
 
     // Returns the value of current state
     fn reward(&self) -> PyResult<f32> {
-        Ok(self.env.reward())
+        Ok(self.env.try_reward().map_err(MyError::from)?)
+    }
+
+    // Returns True if the current state counts as a success
+    fn is_success(&self) -> PyResult<bool> {
+        Ok(self.env.try_is_success().map_err(MyError::from)?)
     }
 
     // Returns current state encoded in a sparse format
     fn observe(&self) -> PyResult<Vec<usize>> {
-        Ok(self.env.observe())
+        Ok(self.env.try_observe().map_err(MyError::from)?)
+    }
+
+    // Returns current state as a sparse, weighted or dense observation
+    fn observation(&self) -> PyResult<Observation> {
+        Ok(self.env.try_observation().map_err(MyError::from)?)
+    }
+
+    // Returns a key that uniquely identifies the current state
+    fn state_key(&self) -> PyResult<Vec<i64>> {
+        Ok(self.env.try_state_key().map_err(MyError::from)?)
+    }
+
+    // Returns a hash of the current state key
+    fn state_hash(&self) -> PyResult<u64> {
+        Ok(self.env.try_state_hash().map_err(MyError::from)?)
+    }
+
+    fn __hash__(&self) -> PyResult<u64> {
+        self.state_hash()
+    }
+
+    // Envs are equal if they are in the same state
+    fn __eq__(&self, other: PyRef<'_, PyBaseEnv>) -> PyResult<bool> {
+        let key = self.env.try_state_key().map_err(MyError::from)?;
+        Ok(key == other.env.try_state_key().map_err(MyError::from)?)
     }
 
     // Returns a list of possible permutations on the observations and the corresponding permutations on actions
-    fn twists(&self) -> PyResult<(Vec<Vec<usize>>, Vec<Vec<usize>>)> {
+    fn twists(&self) -> PyResult<Twists> {
         Ok(self.env.twists())
     }
 
+    // Returns a copy of the env in which stepping an action that is not allowed by the masks raises an error
+    fn strict(&self) -> PyResult<PyBaseEnv> {
+        Ok(PyBaseEnv { env: Box::new(StrictEnv::new(self.env.clone())) })
+    }
+
     // Hidden method to extract the Rust env as a pointer
     fn __extract_env__(&self) -> PyResult<usize> {
         // Return the Box pointer itself as a usize
@@ -121,16 +303,31 @@ pub struct PyPuzzleEnv;
 #[pymethods]
 impl PyPuzzleEnv {
     #[new]
+    #[pyo3(signature = (width, height, difficulty, depth_slope, max_depth, goal=None, goal_in_obs=false, reset_mode="scramble", use_twists=false))]
+    // Mirrors the keyword arguments of the Python API
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: usize,
         height: usize,
         difficulty: usize,
         depth_slope: usize,
         max_depth: usize,
-    ) -> (Self, PyBaseEnv) {
-        let puzzle = Puzzle::new(width, height, difficulty, depth_slope, max_depth);
+        goal: Option<Vec<i64>>,
+        goal_in_obs: bool,
+        reset_mode: &str,
+        use_twists: bool,
+    ) -> PyResult<(Self, PyBaseEnv)> {
+        let mut puzzle = Puzzle::new(width, height, difficulty, depth_slope, max_depth);
+        puzzle.goal_in_obs = goal_in_obs;
+        puzzle.use_twists = use_twists;
+        // "scramble", "uniform" or "exact_distance"
+        puzzle.reset_mode = serde_json::from_value(reset_mode.into())
+            .map_err(|err| MyError::from(anyhow::anyhow!("Invalid reset mode `{}`: {}", reset_mode, err)))?;
+        if let Some(goal) = goal {
+            puzzle.set_goal(goal).map_err(MyError::from)?;
+        }
         let env = Box::new(puzzle);
-        (PyPuzzleEnv, PyBaseEnv { env: env })
+        Ok((PyPuzzleEnv, PyBaseEnv { env }))
     }
 
     pub fn solved(slf: PyRef<'_, Self>) -> PyResult<bool> {
@@ -138,28 +335,118 @@ impl PyPuzzleEnv {
         Ok(puzzle.solved())
     }
 
-    pub fn get_state(slf: PyRef<'_, Self>) -> PyResult<Vec<usize>> {
-        let puzzle = get_env_ref::<Puzzle>(slf.as_ref())?;
-        Ok(puzzle.get_state())
+    pub fn set_position(mut slf: PyRefMut<'_, Self>, x: usize, y: usize, val: usize) -> PyResult<()> {
+        let puzzle = get_env_mut::<Puzzle>(slf.as_mut())?;
+        puzzle.set_position(x, y, val);
+        Ok(())
     }
 
-    pub fn display(slf: PyRef<'_, Self>) -> PyResult<()> {
+    pub fn get_position(slf: PyRef<'_, Self>, x: usize, y: usize) -> PyResult<usize> {
         let puzzle = get_env_ref::<Puzzle>(slf.as_ref())?;
-        Ok(puzzle.display())
+        Ok(puzzle.get_position(x, y))
     }
 
-    pub fn set_position(mut slf: PyRefMut<'_, Self>, x: usize, y: usize, val: usize) -> PyResult<()> {
-        let puzzle = get_env_mut::<Puzzle>(slf.as_mut())?;
-        Ok(puzzle.set_position(x, y, val))
+    // Shortest list of actions from the current state to the goal, or None if it is unsolvable.
+    // A pattern database built for this puzzle speeds up the search.
+    #[pyo3(signature = (pdb=None))]
+    pub fn optimal_solution(slf: PyRef<'_, Self>, pdb: Option<PyRef<'_, PyPatternDatabase>>) -> PyResult<Option<Vec<usize>>> {
+        let puzzle = get_env_ref::<Puzzle>(slf.as_ref())?.clone();
+        let pdb = pdb.as_ref().map(|pdb| &pdb.pdb);
+        match pdb {
+            Some(pdb) => Ok(slf.py().allow_threads(|| puzzle.optimal_solution_with(pdb)).map_err(MyError::from)?),
+            None => Ok(slf.py().allow_threads(|| puzzle.optimal_solution())),
+        }
     }
 
-    pub fn get_position(slf: PyRef<'_, Self>, x: usize, y: usize) -> PyResult<usize> {
+    // Manhattan distance plus linear conflicts of the current state, a lower bound on the moves to the goal
+    pub fn heuristic(slf: PyRef<'_, Self>) -> PyResult<usize> {
         let puzzle = get_env_ref::<Puzzle>(slf.as_ref())?;
-        Ok(puzzle.get_position(x, y))
+        Ok(Solver::new(puzzle).heuristic(&puzzle.state))
     }
 }
 
 
+#[pyclass(name="PermutationPuzzle", extends=PyBaseEnv)]
+pub struct PyPermutationPuzzleEnv;
+
+
+#[pymethods]
+impl PyPermutationPuzzleEnv {
+    // The generators are given either directly (with optional names, and symmetries as (positions, actions) pairs)
+    // or as the path of a JSON generator file
+    #[new]
+    #[pyo3(signature = (difficulty, depth_slope, max_depth, generators=None, generators_file=None, names=None, symmetries=None))]
+    pub fn new(
+        difficulty: usize,
+        depth_slope: usize,
+        max_depth: usize,
+        generators: Option<Vec<Vec<usize>>>,
+        generators_file: Option<String>,
+        names: Option<Vec<String>>,
+        symmetries: Option<Vec<(Vec<usize>, Vec<usize>)>>,
+    ) -> PyResult<(Self, PyBaseEnv)> {
+        let config = PermutationPuzzleConfig {
+            generators,
+            names: names.unwrap_or_default(),
+            symmetries: symmetries.unwrap_or_default().into_iter().map(|(positions, actions)| Symmetry { positions, actions }).collect(),
+            generators_file,
+            difficulty,
+            depth_slope,
+            max_depth,
+        };
+        let generator_set = config.generator_set().map_err(MyError::from)?;
+        let env = PermutationPuzzle::new(generator_set, difficulty, depth_slope, max_depth).map_err(MyError::from)?;
+        Ok((PyPermutationPuzzleEnv, PyBaseEnv { env: Box::new(env) }))
+    }
+
+    pub fn solved(slf: PyRef<'_, Self>) -> PyResult<bool> {
+        let env = get_env_ref::<PermutationPuzzle>(slf.as_ref())?;
+        Ok(env.solved())
+    }
+}
+
+
+// Additive pattern database of a Puzzle, see envs::puzzle::pdb
+#[pyclass(name="PatternDatabase")]
+pub struct PyPatternDatabase {
+    pub pdb: PatternDatabase,
+}
+
+
+#[pymethods]
+impl PyPatternDatabase {
+    // Builds the database for the board size and goal of the puzzle, from disjoint lists of tiles
+    #[new]
+    pub fn new(py: Python<'_>, puzzle: PyRef<'_, PyPuzzleEnv>, patterns: Vec<Vec<usize>>) -> PyResult<Self> {
+        let puzzle = get_env_ref::<Puzzle>(puzzle.as_ref())?.clone();
+        let pdb = py.allow_threads(|| PatternDatabase::build(&puzzle, patterns)).map_err(MyError::from)?;
+        Ok(PyPatternDatabase { pdb })
+    }
+
+    #[staticmethod]
+    pub fn load(path: &str) -> PyResult<Self> {
+        Ok(PyPatternDatabase { pdb: PatternDatabase::load(path).map_err(MyError::from)? })
+    }
+
+    pub fn save(&self, path: &str) -> PyResult<()> {
+        Ok(self.pdb.save(path).map_err(MyError::from)?)
+    }
+
+    #[getter]
+    pub fn patterns(&self) -> Vec<Vec<usize>> {
+        self.pdb.patterns.clone()
+    }
+
+    // Lower bound on the number of moves from the board (as given by get_state) to the goal.
+    // It can also be used as a value target to pretrain the value net.
+    pub fn heuristic(&self, board: Vec<usize>) -> PyResult<usize> {
+        self.pdb.heuristic(&board).map_err(|err| pyo3::exceptions::PyValueError::new_err(err.to_string()))
+    }
+}
+
+
+// The env stays boxed, as stored in PyBaseEnv, so that it can be cloned with `clone_box`
+#[allow(clippy::borrowed_box)]
 pub fn get_env<'a>(py_env: &'a Bound<'_, PyAny>) -> PyResult<&'a Box<dyn Env>> {
     // try to call __extract_env__ on the Python side
     let ptr_val = match py_env.call_method0("__extract_env__") {
@@ -177,7 +464,10 @@ pub fn get_env<'a>(py_env: &'a Bound<'_, PyAny>) -> PyResult<&'a Box<dyn Env>> {
 }
 
 
+// Mirrors the keyword arguments of the Python API, including `C`
+#[allow(non_snake_case, clippy::too_many_arguments)]
 #[pyfunction(name = "solve")]
+#[pyo3(signature = (py_env, policy, deterministic, num_searches, num_mcts_searches, C, max_expand_depth, seed=None))]
 pub fn solve_py(
     py_env: &Bound<'_, PyAny>,
     policy: &PyPolicy,
@@ -185,12 +475,17 @@ pub fn solve_py(
     num_searches: usize,
     num_mcts_searches: usize,
     C: f32,
-    max_expand_depth: usize) -> PyResult<((f32, f32), Vec<usize>)> {
+    max_expand_depth: usize,
+    seed: Option<u64>) -> PyResult<((f32, f32), Vec<usize>)> {
         let env_ref = get_env(py_env)?;
-        Ok(solve(env_ref, &*policy.policy, deterministic, num_searches, num_mcts_searches, C, max_expand_depth))
+        // Without a seed the search is not reproducible
+        let seed = seed.unwrap_or_else(rand::random);
+        Ok(solve(env_ref, &policy.policy, deterministic, num_searches, num_mcts_searches, C, max_expand_depth, seed).map_err(MyError::from)?)
 }
 
 
+// Same as solve
+#[allow(non_snake_case, clippy::too_many_arguments)]
 #[pyfunction(name = "evaluate")]
 pub fn evaluate_py(py_env: &Bound<'_, PyAny>,
     policy: &PyPolicy,
@@ -198,10 +493,44 @@ pub fn evaluate_py(py_env: &Bound<'_, PyAny>,
     deterministic: bool,
     num_searches: usize,
     num_mcts_searches: usize,
-    seed: usize,          // unused for now
+    seed: u64,
     C: f32,
     max_expand_depth: usize,
     num_cores: usize) -> PyResult<(f32, f32)> {
     let env_ref = get_env(py_env)?;
-    Ok(evaluate(env_ref, &*policy.policy, num_episodes, deterministic, num_searches, num_mcts_searches, seed, C, max_expand_depth, num_cores).map_err(MyError::from)?)
-}
\ No newline at end of file
+    Ok(evaluate(env_ref, &policy.policy, num_episodes, deterministic, num_searches, num_mcts_searches, seed, C, max_expand_depth, num_cores).map_err(MyError::from)?)
+}
+
+
+// Builds the env registered under the given name from its JSON config
+#[pyfunction(name = "make_env")]
+pub fn make_env_py(name: &str, config: &str) -> PyResult<PyBaseEnv> {
+    let env = registry::make_env_from_json(name, config).map_err(MyError::from)?;
+    Ok(PyBaseEnv { env })
+}
+
+
+// Returns the names of the envs that can be built with make_env
+#[pyfunction(name = "registered_envs")]
+pub fn registered_envs_py() -> Vec<String> {
+    registry::registered_envs()
+}
+
+
+// Random-walks the env to check that its twists are symmetries. Returns the first counterexample found, or None.
+#[pyfunction(name = "check_twists")]
+#[pyo3(signature = (py_env, num_walks=100, walk_length=20, seed=0))]
+pub fn check_twists_py(py_env: &Bound<'_, PyAny>, num_walks: usize, walk_length: usize, seed: u64) -> PyResult<Option<String>> {
+    let env_ref = get_env(py_env)?;
+    let counterexample = check_twists(env_ref.as_ref(), num_walks, walk_length, seed).map_err(MyError::from)?;
+    Ok(counterexample.map(|c| c.to_string()))
+}
+
+
+// Plays random episodes to check that the env obeys the Env contract. Raises an error describing the first violation.
+#[pyfunction(name = "check_env")]
+#[pyo3(signature = (py_env, num_episodes=10, max_steps=1000, seed=0))]
+pub fn check_env_py(py_env: &Bound<'_, PyAny>, num_episodes: usize, max_steps: usize, seed: u64) -> PyResult<()> {
+    let env_ref = get_env(py_env)?;
+    Ok(check_env(env_ref.as_ref(), num_episodes, max_steps, seed).map_err(MyError::from)?)
+}
@@ -14,6 +14,7 @@ that they have been altered from the originals.
 use pyo3::prelude::*;
 use pyo3::exceptions::PyRuntimeError;
 use anyhow::Error as AnyhowError;
+use crate::rl::env::EnvError;
 
 // Code to map Rust errors to Python exceptions
 // Wrapper for anyhow::Error
@@ -26,6 +27,13 @@ impl From<AnyhowError> for MyError {
     }
 }
 
+// Errors raised by fallible env methods
+impl From<EnvError> for MyError {
+    fn from(err: EnvError) -> MyError {
+        MyError(err.into())
+    }
+}
+
 impl From<MyError> for PyErr {
     fn from(err: MyError) -> PyErr {
         PyRuntimeError::new_err(err.0.to_string())
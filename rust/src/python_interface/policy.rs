@@ -12,10 +12,20 @@ that they have been altered from the originals.
 */
 
 use pyo3::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use crate::python_interface::modules::PySequential;
 use crate::python_interface::layers::PyEmbeddingBag;
 use crate::nn::policy::Policy;
+use crate::python_interface::error_mapping::MyError;
+use crate::rl::env::Observation;
+use crate::rl::rng::seeded_rng;
+
+// Seeded generator if a seed is given, otherwise one seeded from system entropy
+fn make_rng(seed: Option<u64>) -> StdRng {
+    seed.map(seeded_rng).unwrap_or_else(StdRng::from_entropy)
+}
 
 #[pyclass(name="Policy")]
 pub struct PyPolicy {
@@ -25,21 +35,25 @@ pub struct PyPolicy {
 #[pymethods]
 impl PyPolicy {
     #[new]
-    pub fn new(embeddings: PyEmbeddingBag, common: PySequential, action_net: PySequential, value_net: PySequential, obs_perms: Vec<Vec<usize>>, act_perms: Vec<Vec<usize>>) -> Self {
-        let policy = Box::new(Policy::new(embeddings.embedding, common.seq, action_net.seq, value_net.seq, obs_perms, act_perms));
-        PyPolicy { policy }
+    #[pyo3(signature = (embeddings, common, action_net, value_net, obs_perms, act_perms, action_dims=None))]
+    pub fn new(embeddings: PyEmbeddingBag, common: PySequential, action_net: PySequential, value_net: PySequential, obs_perms: Vec<Vec<usize>>, act_perms: Vec<Vec<usize>>, action_dims: Option<Vec<usize>>) -> PyResult<Self> {
+        let policy = Policy::new(embeddings.embedding, common.seq, action_net.seq, value_net.seq, obs_perms, act_perms);
+        let policy = Box::new(policy.with_action_dims(action_dims.unwrap_or_default()).map_err(MyError::from)?);
+        Ok(PyPolicy { policy })
     }
 
-    pub fn predict(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
-        self.policy.predict(obs, masks)
+    #[pyo3(signature = (obs, masks, seed=None))]
+    pub fn predict(&self, obs: Observation, masks: Vec<bool>, seed: Option<u64>) -> (Vec<f32>, f32) {
+        self.policy.predict(obs, masks, &mut make_rng(seed))
     }
 
 
-    pub fn forward(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
-        self.policy.forward(obs, masks)
+    #[pyo3(signature = (obs, masks, seed=None))]
+    pub fn forward(&self, obs: Observation, masks: Vec<bool>, seed: Option<u64>) -> (Vec<f32>, f32) {
+        self.policy.forward(obs, masks, &mut make_rng(seed))
     }
 
-    pub fn full_predict(&self, obs: Vec<usize>, masks: Vec<bool>) -> (Vec<f32>, f32) {
+    pub fn full_predict(&self, obs: Observation, masks: Vec<bool>) -> (Vec<f32>, f32) {
         self.policy.full_predict(obs, masks)
     }
 
@@ -15,7 +15,7 @@ use std::borrow::Borrow;
 
 use pyo3::prelude::*;
 use pyo3::types::PyAny;
-use crate::rl::env::Env;
+use crate::rl::env::{Env, EnvError, EnvResult, Observation, component_masks_from_flat};
 use crate::python_interface::env::PyBaseEnv;
 
 pub struct PyEnvImpl {
@@ -62,12 +62,52 @@ impl Env for PyEnvImpl {
         self.difficulty
     }
 
+    fn set_seed(&mut self, seed: u64) {
+        self.try_set_seed(seed).unwrap_or_else(|err| panic!("{}", err))
+    }
+
     fn set_state(&mut self, state: Vec<i64>) {
+        self.try_set_state(state).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Python envs can be walked by the search if they implement `undo`
+    fn supports_undo(&self) -> bool {
+        Python::with_gil(|py| self.py_env.bind(py).hasattr("undo").unwrap_or(false))
+    }
+
+    fn is_stochastic(&self) -> bool {
+        self.try_is_stochastic().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn undo(&mut self, action: usize) -> EnvResult<()> {
+        if !self.supports_undo() {
+            return Err(EnvError::Unsupported { method: "undo" });
+        }
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
-            py_env.call_method1(py, "set_state", (state,))
-                .expect("Python `set_state` method failed.");
-        });
+            py_env.call_method1(py, "undo", (action,)).map_err(env_error("undo"))?;
+            Ok(())
+        })
+    }
+
+    // Python envs without a `set_goal` method are not goal-conditioned
+    fn set_goal(&mut self, goal: Vec<i64>) -> EnvResult<()> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("set_goal").map_err(env_error("set_goal"))? {
+                return Err(EnvError::Unsupported { method: "set_goal" });
+            }
+            py_env.call_method1("set_goal", (goal,)).map_err(env_error("set_goal"))?;
+            Ok(())
+        })
+    }
+
+    fn get_goal(&self) -> Option<Vec<i64>> {
+        self.try_get_goal().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn get_state(&self) -> Vec<i64> {
+        self.try_get_state().unwrap_or_else(|err| panic!("{}", err))
     }
 
     fn num_actions(&self) -> usize {
@@ -80,6 +120,14 @@ impl Env for PyEnvImpl {
         })
     }
 
+    fn action_dims(&self) -> Vec<usize> {
+        self.try_action_dims().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn component_masks(&self, prefix: &[usize]) -> Vec<bool> {
+        self.try_component_masks(prefix).unwrap_or_else(|err| panic!("{}", err))
+    }
+
     fn obs_shape(&self) -> Vec<usize> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
@@ -90,65 +138,358 @@ impl Env for PyEnvImpl {
         })
     }
     
+    fn render(&self) -> String {
+        self.try_render().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn render_ansi(&self) -> String {
+        self.try_render_ansi().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn action_name(&self, action: usize) -> String {
+        self.try_action_name(action).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn obs_feature_name(&self, index: usize) -> String {
+        self.try_obs_feature_name(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn num_players(&self) -> usize {
+        self.try_num_players().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn current_player(&self) -> usize {
+        self.try_current_player().unwrap_or_else(|err| panic!("{}", err))
+    }
+
     fn reset(&mut self) {
+        self.try_reset().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn step(&mut self, action: usize) {
+        self.try_step(action).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn masks(&self) -> Vec<bool> {
+        self.try_masks().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn is_final(&self) -> bool {
+        self.try_is_final().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.try_is_truncated().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn reward(&self) -> f32 {
+        self.try_reward().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn is_success(&self) -> bool {
+        self.try_is_success().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn observe(&self) -> Vec<usize> {
+        self.try_observe().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn observation(&self) -> Observation {
+        self.try_observation().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn state_key(&self) -> Vec<i64> {
+        self.try_state_key().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Forwards the seed to the Python env, if it implements `set_seed`
+    fn try_set_seed(&mut self, seed: u64) -> EnvResult<()> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if py_env.hasattr("set_seed").map_err(env_error("set_seed"))? {
+                py_env.call_method1("set_seed", (seed,)).map_err(env_error("set_seed"))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn try_set_state(&mut self, state: Vec<i64>) -> EnvResult<()> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.borrow();
+            py_env.call_method1(py, "set_state", (state,)).map_err(env_error("set_state"))?;
+            Ok(())
+        })
+    }
+
+    fn try_get_state(&self) -> EnvResult<Vec<i64>> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
             py_env
-                .call_method1(py, "reset", (self.difficulty,))
-                .expect("Python `reset` method failed.");
-        });
+                .call_method0(py, "get_state")
+                .and_then(|val| val.extract::<Vec<i64>>(py))
+                .map_err(env_error("get_state"))
+        })
     }
 
-    fn step(&mut self, action: usize) {
+    fn try_reset(&mut self) -> EnvResult<()> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.borrow();
+            py_env.call_method1(py, "reset", (self.difficulty,)).map_err(env_error("reset"))?;
+            Ok(())
+        })
+    }
+
+    fn try_step(&mut self, action: usize) -> EnvResult<()> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
+            py_env.call_method1(py, "next", (action,)).map_err(env_error("next"))?;
+            Ok(())
+        })
+    }
+
+    // Python envs without an `action_dims` method have flat action spaces
+    fn try_action_dims(&self) -> EnvResult<Vec<usize>> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("action_dims").map_err(env_error("action_dims"))? {
+                return Ok(vec![self.num_actions()]);
+            }
             py_env
-                .call_method1(py, "next", (action,))
-                .expect("Python `next` method failed.");
-        });
+                .call_method0("action_dims")
+                .and_then(|val| val.extract::<Vec<usize>>())
+                .map_err(env_error("action_dims"))
+        })
     }
 
-    fn masks(&self) -> Vec<bool> {
+    // Python envs without a `get_goal` method have no goal
+    fn try_get_goal(&self) -> EnvResult<Option<Vec<i64>>> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("get_goal").map_err(env_error("get_goal"))? {
+                return Ok(None);
+            }
+            py_env
+                .call_method0("get_goal")
+                .and_then(|val| val.extract::<Option<Vec<i64>>>())
+                .map_err(env_error("get_goal"))
+        })
+    }
+
+    // Python envs may render themselves with `render` (and `render_ansi`) methods
+    fn try_render(&self) -> EnvResult<String> {
+        let has_render = Python::with_gil(|py| self.py_env.bind(py).hasattr("render").map_err(env_error("render")))?;
+        if !has_render {
+            return Ok(format!("{:?}", self.try_get_state()?));
+        }
+        Python::with_gil(|py| {
+            self.py_env.bind(py)
+                .call_method0("render")
+                .and_then(|val| val.extract::<String>())
+                .map_err(env_error("render"))
+        })
+    }
+
+    fn try_render_ansi(&self) -> EnvResult<String> {
+        let has_render_ansi = Python::with_gil(|py| self.py_env.bind(py).hasattr("render_ansi").map_err(env_error("render_ansi")))?;
+        if !has_render_ansi {
+            return self.try_render();
+        }
+        Python::with_gil(|py| {
+            self.py_env.bind(py)
+                .call_method0("render_ansi")
+                .and_then(|val| val.extract::<String>())
+                .map_err(env_error("render_ansi"))
+        })
+    }
+
+    // Python envs may name their actions with an `action_name` method
+    fn try_action_name(&self, action: usize) -> EnvResult<String> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("action_name").map_err(env_error("action_name"))? {
+                return Ok(action.to_string());
+            }
+            py_env
+                .call_method1("action_name", (action,))
+                .and_then(|val| val.extract::<String>())
+                .map_err(env_error("action_name"))
+        })
+    }
+
+    fn try_action_names(&self) -> EnvResult<Vec<String>> {
+        (0..self.num_actions()).map(|action| self.try_action_name(action)).collect()
+    }
+
+    fn try_obs_feature_name(&self, index: usize) -> EnvResult<String> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("obs_feature_name").map_err(env_error("obs_feature_name"))? {
+                return Ok(index.to_string());
+            }
+            py_env
+                .call_method1("obs_feature_name", (index,))
+                .and_then(|val| val.extract::<String>())
+                .map_err(env_error("obs_feature_name"))
+        })
+    }
+
+    // Python envs are deterministic unless they implement `is_stochastic`
+    fn try_is_stochastic(&self) -> EnvResult<bool> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("is_stochastic").map_err(env_error("is_stochastic"))? {
+                return Ok(false);
+            }
+            py_env
+                .call_method0("is_stochastic")
+                .and_then(|val| val.extract::<bool>())
+                .map_err(env_error("is_stochastic"))
+        })
+    }
+
+    // Python envs without a `num_players` method are single-player
+    fn try_num_players(&self) -> EnvResult<usize> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("num_players").map_err(env_error("num_players"))? {
+                return Ok(1);
+            }
+            py_env
+                .call_method0("num_players")
+                .and_then(|val| val.extract::<usize>())
+                .map_err(env_error("num_players"))
+        })
+    }
+
+    fn try_current_player(&self) -> EnvResult<usize> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("current_player").map_err(env_error("current_player"))? {
+                return Ok(0);
+            }
+            py_env
+                .call_method0("current_player")
+                .and_then(|val| val.extract::<usize>())
+                .map_err(env_error("current_player"))
+        })
+    }
+
+    fn try_masks(&self) -> EnvResult<Vec<bool>> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
             py_env
                 .call_method0(py, "masks")
                 .and_then(|val| val.extract::<Vec<bool>>(py))
-                .expect("Python `masks` method must return a list of booleans.")
+                .map_err(env_error("masks"))
         })
     }
 
-    fn is_final(&self) -> bool {
+    // Uses the Python `component_masks` method if the env has one, otherwise the flat masks
+    fn try_component_masks(&self, prefix: &[usize]) -> EnvResult<Vec<bool>> {
+        let has_component_masks = Python::with_gil(|py| {
+            self.py_env.bind(py).hasattr("component_masks").map_err(env_error("component_masks"))
+        })?;
+        if !has_component_masks {
+            return Ok(component_masks_from_flat(&self.try_action_dims()?, &self.try_masks()?, prefix));
+        }
+        Python::with_gil(|py| {
+            let py_env = self.py_env.borrow();
+            py_env
+                .call_method1(py, "component_masks", (prefix.to_vec(),))
+                .and_then(|val| val.extract::<Vec<bool>>(py))
+                .map_err(env_error("component_masks"))
+        })
+    }
+
+    fn try_is_final(&self) -> EnvResult<bool> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
             py_env
                 .call_method0(py, "is_final")
                 .and_then(|val| val.extract::<bool>(py))
-                .expect("Python `is_final` method must return a boolean.")
+                .map_err(env_error("is_final"))
         })
     }
 
-    fn reward(&self) -> f32 {
+    // Python envs without an `is_truncated` method never truncate
+    fn try_is_truncated(&self) -> EnvResult<bool> {
+        Python::with_gil(|py| {
+            let py_env = self.py_env.bind(py);
+            if !py_env.hasattr("is_truncated").map_err(env_error("is_truncated"))? {
+                return Ok(false);
+            }
+            py_env
+                .call_method0("is_truncated")
+                .and_then(|val| val.extract::<bool>())
+                .map_err(env_error("is_truncated"))
+        })
+    }
+
+    fn try_reward(&self) -> EnvResult<f32> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
             py_env
                 .call_method0(py, "value")
                 .and_then(|val| val.extract::<f32>(py))
-                .expect("Python `value` method must return a float.")
+                .map_err(env_error("value"))
         })
     }
 
-    fn observe(&self) -> Vec<usize> {
+    // Python envs without an `is_success` method use the default reward convention
+    fn try_is_success(&self) -> EnvResult<bool> {
+        let has_is_success = Python::with_gil(|py| {
+            self.py_env.bind(py).hasattr("is_success").map_err(env_error("is_success"))
+        })?;
+        if !has_is_success {
+            return Ok(self.try_reward()? == 1.0);
+        }
+        Python::with_gil(|py| {
+            let py_env = self.py_env.borrow();
+            py_env
+                .call_method0(py, "is_success")
+                .and_then(|val| val.extract::<bool>(py))
+                .map_err(env_error("is_success"))
+        })
+    }
+
+    fn try_observe(&self) -> EnvResult<Vec<usize>> {
+        Ok(self.try_observation()?.active_indices())
+    }
+
+    // The Python `observe` method may return any of the observation encodings
+    fn try_observation(&self) -> EnvResult<Observation> {
         Python::with_gil(|py| {
             let py_env = self.py_env.borrow();
             py_env
                 .call_method0(py, "observe")
-                .and_then(|val| val.extract::<Vec<usize>>(py))
-                .expect("Python `observe` method must return a list of integers.")
+                .and_then(|val| val.extract::<Observation>(py))
+                .map_err(env_error("observe"))
+        })
+    }
+
+    // Uses the Python `key` method if the env has one, otherwise the observation
+    fn try_state_key(&self) -> EnvResult<Vec<i64>> {
+        let has_key = Python::with_gil(|py| {
+            self.py_env.bind(py).hasattr("key").map_err(env_error("key"))
+        })?;
+        if !has_key {
+            return Ok(self.try_observation()?.to_key());
+        }
+        Python::with_gil(|py| {
+            let py_env = self.py_env.borrow();
+            py_env
+                .call_method0(py, "key")
+                .and_then(|val| val.extract::<Vec<i64>>(py))
+                .map_err(env_error("key"))
         })
     }
 }
 
+// Maps a Python exception raised by the given method of the Python env to an `EnvError`
+fn env_error(method: &'static str) -> impl Fn(PyErr) -> EnvError {
+    move |err| EnvError::failed(method, err)
+}
+
 
 #[pyclass(subclass, extends=PyBaseEnv)]
 pub struct PyEnv {}
@@ -159,6 +500,6 @@ impl PyEnv {
     pub fn new(py_env: Py<PyAny>) -> (Self, PyBaseEnv) {
         let env_impl = PyEnvImpl::new(py_env);
         let env = Box::new(env_impl);
-        (PyEnv {}, PyBaseEnv { env: env })
+        (PyEnv {}, PyBaseEnv { env })
     }
 }
\ No newline at end of file
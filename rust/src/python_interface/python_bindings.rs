@@ -29,12 +29,18 @@ fn init_nn_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
 // Env Module
 // use crate::envs::puzzle::Puzzle;
 use crate::python_interface::pyenv::PyEnv;
-use crate::python_interface::env::{PyPuzzleEnv, PyBaseEnv, solve_py, evaluate_py};
+use crate::python_interface::env::{PyPuzzleEnv, PyPermutationPuzzleEnv, PyPatternDatabase, PyBaseEnv, solve_py, evaluate_py, make_env_py, registered_envs_py, check_twists_py, check_env_py};
 
 fn init_env_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyPuzzleEnv>()?;
+    m.add_class::<PyPermutationPuzzleEnv>()?;
+    m.add_class::<PyPatternDatabase>()?;
     m.add_class::<PyBaseEnv>()?;
     m.add_class::<PyEnv>()?;
+    m.add_function(wrap_pyfunction!(make_env_py, m)?)?;
+    m.add_function(wrap_pyfunction!(registered_envs_py, m)?)?;
+    m.add_function(wrap_pyfunction!(check_twists_py, m)?)?;
+    m.add_function(wrap_pyfunction!(check_env_py, m)?)?;
     Ok(())
 }
 
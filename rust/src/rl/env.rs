@@ -13,8 +13,149 @@ that they have been altered from the originals.
 
 use std::vec;
 use std::any::Any;
+use std::fmt;
 use dyn_clone::DynClone;
 
+use crate::rl::vec_env::VecEnv;
+use crate::rl::rng::derive_seed;
+
+pub mod strict;
+pub mod testing;
+
+// Errors returned by the fallible (`try_*`) env methods
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvError {
+    // The env implementation failed while running the named method (e.g. a Python exception)
+    Failed { method: &'static str, message: String },
+    // The env does not implement the named optional method (e.g. `undo`)
+    Unsupported { method: &'static str },
+    // The action is not allowed by the masks of the current state
+    InvalidAction { action: usize, masks: Vec<bool> },
+}
+
+impl EnvError {
+    pub fn failed(method: &'static str, message: impl ToString) -> Self {
+        EnvError::Failed { method, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvError::Failed { method, message } => write!(f, "Env method `{}` failed: {}", method, message),
+            EnvError::Unsupported { method } => write!(f, "Env method `{}` is not supported by this env", method),
+            EnvError::InvalidAction { action, masks } => write!(f, "Action {} is not allowed, the action masks are {:?}", action, masks),
+        }
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+pub type EnvResult<T> = Result<T, EnvError>;
+
+// Observation of a state, in one of the supported encodings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observation {
+    // Indices of the active features (all with value 1)
+    Sparse(Vec<usize>),
+    // (index, value) pairs of the non-zero features
+    Weighted(Vec<(usize, f32)>),
+    // Value of every feature
+    Dense(Vec<f32>),
+}
+
+impl Observation {
+    // Returns the (index, value) pairs of all the features that are present
+    pub fn to_weighted(&self) -> Vec<(usize, f32)> {
+        match self {
+            Observation::Sparse(indices) => indices.iter().map(|&i| (i, 1.0)).collect(),
+            Observation::Weighted(pairs) => pairs.clone(),
+            Observation::Dense(values) => values.iter().enumerate()
+                .filter(|(_, &v)| v != 0.0)
+                .map(|(i, &v)| (i, v))
+                .collect(),
+        }
+    }
+
+    // Returns the indices of the features with a non-zero value
+    pub fn active_indices(&self) -> Vec<usize> {
+        match self {
+            Observation::Sparse(indices) => indices.clone(),
+            _ => self.to_weighted().into_iter().filter(|&(_, v)| v != 0.0).map(|(i, _)| i).collect(),
+        }
+    }
+
+    // Moves the feature at index i to index perm[i]
+    pub fn permute(&self, perm: &[usize]) -> Observation {
+        match self {
+            Observation::Sparse(indices) => Observation::Sparse(indices.iter().map(|&i| perm[i]).collect()),
+            Observation::Weighted(pairs) => Observation::Weighted(pairs.iter().map(|&(i, v)| (perm[i], v)).collect()),
+            Observation::Dense(values) => {
+                let mut permuted = vec![0.0; values.len()];
+                for (i, &v) in values.iter().enumerate() {
+                    permuted[perm[i]] = v;
+                }
+                Observation::Dense(permuted)
+            }
+        }
+    }
+
+    // Returns a key that identifies the observation (values are encoded by their bit pattern)
+    pub fn to_key(&self) -> Vec<i64> {
+        match self {
+            Observation::Sparse(indices) => indices.iter().map(|&i| i as i64).collect(),
+            _ => self.to_weighted().into_iter().flat_map(|(i, v)| [i as i64, v.to_bits() as i64]).collect(),
+        }
+    }
+}
+
+// Hashes a state key into a single value. The hash is fixed (a splitmix64 fold), so it is the same
+// across runs, builds and Rust versions.
+pub fn hash_state_key(key: &[i64]) -> u64 {
+    key.iter().fold(key.len() as u64, |hash, &value| derive_seed(hash, value as u64))
+}
+
+// Returns the flat index of a factored action, given its components (the first one is the most significant)
+pub fn encode_action(action_dims: &[usize], components: &[usize]) -> usize {
+    components.iter().zip(action_dims.iter()).fold(0, |action, (&c, &d)| action * d + c)
+}
+
+// Returns the components of a factored action, given its flat index
+pub fn decode_action(action_dims: &[usize], mut action: usize) -> Vec<usize> {
+    let mut components = vec![0; action_dims.len()];
+    for (c, &d) in components.iter_mut().zip(action_dims.iter()).rev() {
+        *c = action % d;
+        action /= d;
+    }
+    components
+}
+
+// Masks of the next action component given the previous ones: a value is allowed if any allowed flat action starts with prefix + value
+pub fn component_masks_from_flat(action_dims: &[usize], masks: &[bool], prefix: &[usize]) -> Vec<bool> {
+    let dim = action_dims[prefix.len()];
+    let stride: usize = action_dims[prefix.len() + 1..].iter().product();
+    let base = encode_action(action_dims, prefix) * dim;
+    (0..dim).map(|c| masks[(base + c) * stride..(base + c + 1) * stride].iter().any(|&m| m)).collect()
+}
+
+impl From<Vec<usize>> for Observation {
+    fn from(indices: Vec<usize>) -> Self {
+        Observation::Sparse(indices)
+    }
+}
+
+impl From<Vec<(usize, f32)>> for Observation {
+    fn from(pairs: Vec<(usize, f32)>) -> Self {
+        Observation::Weighted(pairs)
+    }
+}
+
+impl From<Vec<f32>> for Observation {
+    fn from(values: Vec<f32>) -> Self {
+        Observation::Dense(values)
+    }
+}
+
 pub trait Env : DynClone + Send + Sync {
     // Methods for downcasting
     fn as_any(&self) -> &dyn Any;
@@ -22,38 +163,202 @@ pub trait Env : DynClone + Send + Sync {
     // Returns the number of possible actions
     fn num_actions(&self) -> usize;
 
+    // Returns the sizes of the components of factored actions, e.g. [gate, qubit, qubit]. Flat actions are
+    // then given by encode_action, and their product must be num_actions. Flat envs have a single component.
+    fn action_dims(&self) -> Vec<usize> {vec![self.num_actions()]}
+
+    // Returns the masks of the next action component, given the components already chosen.
+    // Factored envs can override it to avoid going through the flat masks.
+    fn component_masks(&self, prefix: &[usize]) -> Vec<bool> {
+        component_masks_from_flat(&self.action_dims(), &self.masks(), prefix)
+    }
+
     // Returns the size of the observations 
     fn obs_shape(&self) -> Vec<usize>;
 
+    // Returns a text rendering of the current state
+    fn render(&self) -> String {format!("{:?}", self.get_state())}
+
+    // Same as render, but may use ANSI escape codes (e.g. colors) for terminals
+    fn render_ansi(&self) -> String {self.render()}
+
+    // Returns a human readable name of the action
+    fn action_name(&self, action: usize) -> String {action.to_string()}
+
+    // Returns the names of all the actions
+    fn action_names(&self) -> Vec<String> {(0..self.num_actions()).map(|action| self.action_name(action)).collect()}
+
+    // Returns a human readable description of the observation feature with the given index
+    fn obs_feature_name(&self, index: usize) -> String {index.to_string()}
+
+    // Returns the number of players taking turns (1 for puzzles, 2 for two-player zero-sum games)
+    fn num_players(&self) -> usize {1}
+
+    // Returns the player to move. In multi-player envs rewards (and values) are from the point of view of this player.
+    fn current_player(&self) -> usize {0}
+
     // Sets the current difficulty
-    fn set_difficulty(&mut self, difficulty: usize){}
+    fn set_difficulty(&mut self, _difficulty: usize){}
 
     // Returns current difficulty
     fn get_difficulty(&self) -> usize {1}
 
+    // Sets the goal the env has to reach (e.g. a target permutation), for goal-conditioned envs
+    fn set_goal(&mut self, _goal: Vec<i64>) -> EnvResult<()> {
+        Err(EnvError::Unsupported { method: "set_goal" })
+    }
+
+    // Returns the current goal, or None if the env is not goal-conditioned
+    fn get_goal(&self) -> Option<Vec<i64>> {None}
+
     // Sets itself a given input state (constructed from a Vec<usize>)
     fn set_state(&mut self, state: Vec<i64>);
 
+    // Returns the current state in the format accepted by `set_state`
+    fn get_state(&self) -> Vec<i64>;
+
+    // Seeds the random number generator used by `reset` (and any other random behavior of the env)
+    fn set_seed(&mut self, _seed: u64) {}
+
     // Sets itself to a random initial state
     fn reset(&mut self);
 
     // Evolves the current state by an action
     fn step(&mut self, action: usize);
 
+    // Returns True if the env implements `undo`, which lets the search walk a single env instead of cloning it
+    fn supports_undo(&self) -> bool {false}
+
+    // Reverts a previous `step(action)`, where the action was allowed by the masks of a non-final state.
+    // Actions must be undone in the reverse order in which they were applied.
+    fn undo(&mut self, _action: usize) -> EnvResult<()> {
+        Err(EnvError::Unsupported { method: "undo" })
+    }
+
+    // Returns True if `step` has random outcomes. The search then samples a new outcome on every visit,
+    // reseeding a copy of the env with `set_seed`, so `step` must draw its randomness from that seed.
+    fn is_stochastic(&self) -> bool {false}
+
     // Returns an array with the action masks (True if an action is allowed, False if not)
     fn masks(&self) -> Vec<bool> {vec![true; self.num_actions()]}
 
     // Returns True if the given state is a terminal state
     fn is_final(&self) -> bool;
 
+    // Returns True if the episode was cut short (e.g. by a step limit) instead of reaching a terminal state.
+    // Truncated states are also final, but their value should be bootstrapped rather than taken as zero.
+    fn is_truncated(&self) -> bool {false}
+
+    // Returns True if the episode ended in a genuinely terminal state
+    fn is_terminated(&self) -> bool {self.is_final() && !self.is_truncated()}
+
     // Returns the value of current state
     fn reward(&self) -> f32;
 
+    // Returns True if the current state counts as a success (e.g. a solved puzzle).
+    // Defaults to the reward convention of the bundled envs, where success has a reward of 1.
+    fn is_success(&self) -> bool {self.reward() == 1.0}
+
     // Returns current state encoded in a sparse format
     fn observe(&self) -> Vec<usize>;
 
+    // Returns current state in any of the supported encodings (sparse, weighted or dense).
+    // Envs with real-valued features override this, and `observe` returns their active indices.
+    fn observation(&self) -> Observation {Observation::Sparse(self.observe())}
+
+    // Returns a key that uniquely identifies the current state (two states are the same iff their keys are equal).
+    // Defaults to the observation, envs whose observations do not fully determine the state override this.
+    fn state_key(&self) -> Vec<i64> {self.observation().to_key()}
+
+    // Returns a hash of the current state key
+    fn state_hash(&self) -> u64 {hash_state_key(&self.state_key())}
+
+    // Returns True if both envs are in the same state
+    fn same_state(&self, other: &dyn Env) -> bool {self.state_key() == other.state_key()}
+
+    // Returns a specialized VecEnv with num_envs copies of this env, if the env has one
+    fn vec_env(&self, _num_envs: usize) -> Option<Box<dyn VecEnv>> {None}
+
     // Returns a list of possible permutations on the observations and the corresponding permutations on actions
     fn twists(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {(vec![], vec![])}
+
+    // Fallible versions of the methods above, used by the collectors, solve, evaluate and MCTS.
+    // Envs that can fail (e.g. envs implemented in Python) override these instead of panicking.
+    fn try_set_seed(&mut self, seed: u64) -> EnvResult<()> {
+        self.set_seed(seed);
+        Ok(())
+    }
+
+    fn try_set_state(&mut self, state: Vec<i64>) -> EnvResult<()> {
+        self.set_state(state);
+        Ok(())
+    }
+
+    fn try_get_state(&self) -> EnvResult<Vec<i64>> {Ok(self.get_state())}
+
+    fn try_reset(&mut self) -> EnvResult<()> {
+        self.reset();
+        Ok(())
+    }
+
+    fn try_step(&mut self, action: usize) -> EnvResult<()> {
+        self.step(action);
+        Ok(())
+    }
+
+    fn try_action_dims(&self) -> EnvResult<Vec<usize>> {Ok(self.action_dims())}
+
+    fn try_get_goal(&self) -> EnvResult<Option<Vec<i64>>> {Ok(self.get_goal())}
+
+    fn try_render(&self) -> EnvResult<String> {Ok(self.render())}
+
+    fn try_render_ansi(&self) -> EnvResult<String> {Ok(self.render_ansi())}
+
+    fn try_action_name(&self, action: usize) -> EnvResult<String> {Ok(self.action_name(action))}
+
+    fn try_action_names(&self) -> EnvResult<Vec<String>> {Ok(self.action_names())}
+
+    fn try_obs_feature_name(&self, index: usize) -> EnvResult<String> {Ok(self.obs_feature_name(index))}
+
+    fn try_is_stochastic(&self) -> EnvResult<bool> {Ok(self.is_stochastic())}
+
+    fn try_num_players(&self) -> EnvResult<usize> {Ok(self.num_players())}
+
+    fn try_current_player(&self) -> EnvResult<usize> {Ok(self.current_player())}
+
+    fn try_masks(&self) -> EnvResult<Vec<bool>> {Ok(self.masks())}
+
+    fn try_component_masks(&self, prefix: &[usize]) -> EnvResult<Vec<bool>> {Ok(self.component_masks(prefix))}
+
+    fn try_is_final(&self) -> EnvResult<bool> {Ok(self.is_final())}
+
+    fn try_is_truncated(&self) -> EnvResult<bool> {Ok(self.is_truncated())}
+
+    fn try_reward(&self) -> EnvResult<f32> {Ok(self.reward())}
+
+    fn try_is_success(&self) -> EnvResult<bool> {Ok(self.is_success())}
+
+    fn try_observe(&self) -> EnvResult<Vec<usize>> {Ok(self.observe())}
+
+    fn try_observation(&self) -> EnvResult<Observation> {Ok(self.observation())}
+
+    fn try_state_key(&self) -> EnvResult<Vec<i64>> {Ok(self.state_key())}
+
+    fn try_state_hash(&self) -> EnvResult<u64> {Ok(hash_state_key(&self.try_state_key()?))}
 }
 
-dyn_clone::clone_trait_object!(Env);
\ No newline at end of file
+dyn_clone::clone_trait_object!(Env);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_state_key_is_fixed() {
+        assert_eq!(hash_state_key(&[]), 0);
+        assert_eq!(hash_state_key(&[0, 1, 2, 3]), 11980954370665197445);
+        assert_ne!(hash_state_key(&[0, 1, 2, 3]), hash_state_key(&[1, 0, 2, 3]));
+        assert_ne!(hash_state_key(&[0]), hash_state_key(&[0, 0]));
+    }
+}
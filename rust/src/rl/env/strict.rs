@@ -0,0 +1,149 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use std::any::Any;
+
+use crate::rl::env::{Env, EnvError, EnvResult, Observation};
+use crate::rl::vec_env::VecEnv;
+
+// Returns an InvalidAction error if the action is out of range or masked in the current state of the env
+pub fn validate_action(env: &dyn Env, action: usize) -> EnvResult<()> {
+    let masks = env.try_masks()?;
+    if !masks.get(action).copied().unwrap_or(false) {
+        return Err(EnvError::InvalidAction { action, masks });
+    }
+    Ok(())
+}
+
+// Same as validate_action in debug builds, to catch policy/mask bugs (e.g. NaN logits). Does nothing in release builds.
+pub fn debug_validate_action(env: &dyn Env, action: usize) -> EnvResult<()> {
+    if cfg!(debug_assertions) {
+        validate_action(env, action)?;
+    }
+    Ok(())
+}
+
+// Wraps an env so that stepping an action that is not allowed by the masks is an error (a panic for `step`),
+// instead of whatever the env does with it. Everything else is forwarded to the wrapped env.
+#[derive(Clone)]
+pub struct StrictEnv {
+    pub env: Box<dyn Env>,
+}
+
+impl StrictEnv {
+    pub fn new(env: Box<dyn Env>) -> Self {
+        StrictEnv { env }
+    }
+}
+
+impl Env for StrictEnv {
+    // Downcasting gives the wrapped env
+    fn as_any(&self) -> &dyn Any { self.env.as_any() }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self.env.as_any_mut() }
+
+    fn num_actions(&self) -> usize { self.env.num_actions() }
+    fn action_dims(&self) -> Vec<usize> { self.env.action_dims() }
+    fn component_masks(&self, prefix: &[usize]) -> Vec<bool> { self.env.component_masks(prefix) }
+    fn obs_shape(&self) -> Vec<usize> { self.env.obs_shape() }
+    fn render(&self) -> String { self.env.render() }
+    fn render_ansi(&self) -> String { self.env.render_ansi() }
+    fn action_name(&self, action: usize) -> String { self.env.action_name(action) }
+    fn action_names(&self) -> Vec<String> { self.env.action_names() }
+    fn obs_feature_name(&self, index: usize) -> String { self.env.obs_feature_name(index) }
+    fn num_players(&self) -> usize { self.env.num_players() }
+    fn current_player(&self) -> usize { self.env.current_player() }
+    fn set_difficulty(&mut self, difficulty: usize) { self.env.set_difficulty(difficulty) }
+    fn get_difficulty(&self) -> usize { self.env.get_difficulty() }
+    fn set_goal(&mut self, goal: Vec<i64>) -> EnvResult<()> { self.env.set_goal(goal) }
+    fn get_goal(&self) -> Option<Vec<i64>> { self.env.get_goal() }
+    fn set_state(&mut self, state: Vec<i64>) { self.env.set_state(state) }
+    fn get_state(&self) -> Vec<i64> { self.env.get_state() }
+    fn set_seed(&mut self, seed: u64) { self.env.set_seed(seed) }
+    fn reset(&mut self) { self.env.reset() }
+
+    fn step(&mut self, action: usize) {
+        self.try_step(action).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn supports_undo(&self) -> bool { self.env.supports_undo() }
+    fn undo(&mut self, action: usize) -> EnvResult<()> { self.env.undo(action) }
+    fn is_stochastic(&self) -> bool { self.env.is_stochastic() }
+    fn masks(&self) -> Vec<bool> { self.env.masks() }
+    fn is_final(&self) -> bool { self.env.is_final() }
+    fn is_truncated(&self) -> bool { self.env.is_truncated() }
+    fn is_terminated(&self) -> bool { self.env.is_terminated() }
+    fn reward(&self) -> f32 { self.env.reward() }
+    fn is_success(&self) -> bool { self.env.is_success() }
+    fn observe(&self) -> Vec<usize> { self.env.observe() }
+    fn observation(&self) -> Observation { self.env.observation() }
+    fn state_key(&self) -> Vec<i64> { self.env.state_key() }
+    fn state_hash(&self) -> u64 { self.env.state_hash() }
+    fn twists(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) { self.env.twists() }
+
+    // Specialized VecEnvs step the wrapped envs directly, so batches of strict envs go through the generic one
+    fn vec_env(&self, _num_envs: usize) -> Option<Box<dyn VecEnv>> { None }
+
+    fn try_set_seed(&mut self, seed: u64) -> EnvResult<()> { self.env.try_set_seed(seed) }
+    fn try_set_state(&mut self, state: Vec<i64>) -> EnvResult<()> { self.env.try_set_state(state) }
+    fn try_get_state(&self) -> EnvResult<Vec<i64>> { self.env.try_get_state() }
+    fn try_reset(&mut self) -> EnvResult<()> { self.env.try_reset() }
+
+    fn try_step(&mut self, action: usize) -> EnvResult<()> {
+        validate_action(self.env.as_ref(), action)?;
+        self.env.try_step(action)
+    }
+
+    fn try_action_dims(&self) -> EnvResult<Vec<usize>> { self.env.try_action_dims() }
+    fn try_current_player(&self) -> EnvResult<usize> { self.env.try_current_player() }
+    fn try_masks(&self) -> EnvResult<Vec<bool>> { self.env.try_masks() }
+    fn try_component_masks(&self, prefix: &[usize]) -> EnvResult<Vec<bool>> { self.env.try_component_masks(prefix) }
+    fn try_is_final(&self) -> EnvResult<bool> { self.env.try_is_final() }
+    fn try_is_truncated(&self) -> EnvResult<bool> { self.env.try_is_truncated() }
+    fn try_reward(&self) -> EnvResult<f32> { self.env.try_reward() }
+    fn try_is_success(&self) -> EnvResult<bool> { self.env.try_is_success() }
+    fn try_observe(&self) -> EnvResult<Vec<usize>> { self.env.try_observe() }
+    fn try_observation(&self) -> EnvResult<Observation> { self.env.try_observation() }
+    fn try_state_key(&self) -> EnvResult<Vec<i64>> { self.env.try_state_key() }
+    fn try_state_hash(&self) -> EnvResult<u64> { self.env.try_state_hash() }
+    fn try_num_players(&self) -> EnvResult<usize> { self.env.try_num_players() }
+    fn try_is_stochastic(&self) -> EnvResult<bool> { self.env.try_is_stochastic() }
+    fn try_action_name(&self, action: usize) -> EnvResult<String> { self.env.try_action_name(action) }
+    fn try_action_names(&self) -> EnvResult<Vec<String>> { self.env.try_action_names() }
+    fn try_obs_feature_name(&self, index: usize) -> EnvResult<String> { self.env.try_obs_feature_name(index) }
+    fn try_render(&self) -> EnvResult<String> { self.env.try_render() }
+    fn try_render_ansi(&self) -> EnvResult<String> { self.env.try_render_ansi() }
+    fn try_get_goal(&self) -> EnvResult<Option<Vec<i64>>> { self.env.try_get_goal() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::Puzzle;
+
+    #[test]
+    fn test_strict_env_rejects_masked_actions() {
+        let mut puzzle = Puzzle::new(3, 3, 0, 1, 10);
+        puzzle.set_state((0..9).collect());
+        let mut env = StrictEnv::new(Box::new(puzzle));
+
+        // The blank is in the top left corner, so it can not move left
+        let err = env.try_step(0).unwrap_err();
+        assert_eq!(err, EnvError::InvalidAction { action: 0, masks: vec![false, false, true, true] });
+        assert!(env.try_step(4).is_err());
+        assert_eq!(env.get_state(), (0..9).collect::<Vec<i64>>());
+
+        env.try_step(2).unwrap();
+        assert_eq!(env.get_state()[..2], [1, 0]);
+        assert!(env.as_any().downcast_ref::<Puzzle>().is_some());
+    }
+}
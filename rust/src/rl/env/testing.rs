@@ -0,0 +1,207 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use anyhow::{bail, Result};
+use rand::Rng;
+
+use crate::rl::env::{Env, EnvError, EnvResult, Observation};
+use crate::rl::rng::{derive_seed, seeded_rng};
+use crate::rl::vec_env::VecEnv;
+
+// Minimal env for tests: a single action, and episodes that end after one step
+#[derive(Clone)]
+pub struct DummyEnv { pub step: usize }
+
+impl DummyEnv {
+    pub fn new() -> Self { Self { step: 0 } }
+}
+
+impl Default for DummyEnv {
+    fn default() -> Self { Self::new() }
+}
+
+impl Env for DummyEnv {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
+    fn num_actions(&self) -> usize { 1 }
+    fn obs_shape(&self) -> Vec<usize> { vec![1] }
+    fn set_state(&mut self, state: Vec<i64>) { self.step = state[0] as usize; }
+    fn get_state(&self) -> Vec<i64> { vec![self.step as i64] }
+    fn reset(&mut self) { self.step = 0; }
+    fn step(&mut self, _action: usize) { self.step += 1; }
+    fn masks(&self) -> Vec<bool> { vec![true] }
+    fn is_final(&self) -> bool { self.step >= 1 }
+    fn reward(&self) -> f32 { 1.0 }
+    fn observe(&self) -> Vec<usize> { vec![0] }
+}
+
+// Wraps an env to give it the misbehaviors that tests need, e.g. failing steps or masked actions.
+// Everything that is not overridden is forwarded to the wrapped env.
+#[derive(Clone)]
+pub struct TestEnv {
+    pub env: Box<dyn Env>,
+    // If set, `try_step` fails with this message (like a Python env raising an exception)
+    pub step_error: Option<String>,
+    // If set, the final states count as truncated, so every episode ends by hitting a step limit
+    pub truncated: bool,
+    // If set, replaces the masks of the env, and num_actions is their length
+    pub masks: Option<Vec<bool>>,
+    // If set, replaces the observation shape of the env
+    pub obs_shape: Option<Vec<usize>>,
+    // If set, the env does not support `undo`, so the search clones it into every node
+    pub no_undo: bool,
+}
+
+impl TestEnv {
+    pub fn new(env: Box<dyn Env>) -> Self {
+        TestEnv { env, step_error: None, truncated: false, masks: None, obs_shape: None, no_undo: false }
+    }
+}
+
+impl Env for TestEnv {
+    // Downcasting gives the wrapped env
+    fn as_any(&self) -> &dyn std::any::Any { self.env.as_any() }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self.env.as_any_mut() }
+
+    fn num_actions(&self) -> usize { self.masks.as_ref().map_or_else(|| self.env.num_actions(), |masks| masks.len()) }
+    fn obs_shape(&self) -> Vec<usize> { self.obs_shape.clone().unwrap_or_else(|| self.env.obs_shape()) }
+    fn num_players(&self) -> usize { self.env.num_players() }
+    fn current_player(&self) -> usize { self.env.current_player() }
+    fn set_difficulty(&mut self, difficulty: usize) { self.env.set_difficulty(difficulty) }
+    fn get_difficulty(&self) -> usize { self.env.get_difficulty() }
+    fn set_state(&mut self, state: Vec<i64>) { self.env.set_state(state) }
+    fn get_state(&self) -> Vec<i64> { self.env.get_state() }
+    fn set_seed(&mut self, seed: u64) { self.env.set_seed(seed) }
+    fn reset(&mut self) { self.env.reset() }
+
+    fn step(&mut self, action: usize) {
+        self.try_step(action).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn supports_undo(&self) -> bool { !self.no_undo && self.env.supports_undo() }
+
+    fn undo(&mut self, action: usize) -> EnvResult<()> {
+        if self.no_undo {
+            return Err(EnvError::Unsupported { method: "undo" });
+        }
+        self.env.undo(action)
+    }
+
+    fn is_stochastic(&self) -> bool { self.env.is_stochastic() }
+    fn masks(&self) -> Vec<bool> { self.masks.clone().unwrap_or_else(|| self.env.masks()) }
+    fn is_final(&self) -> bool { self.env.is_final() }
+    fn is_truncated(&self) -> bool { if self.truncated { self.env.is_final() } else { self.env.is_truncated() } }
+    fn reward(&self) -> f32 { self.env.reward() }
+    fn is_success(&self) -> bool { self.env.is_success() }
+    fn observe(&self) -> Vec<usize> { self.env.observe() }
+    fn observation(&self) -> Observation { self.env.observation() }
+    fn state_key(&self) -> Vec<i64> { self.env.state_key() }
+
+    // Specialized VecEnvs step the wrapped envs directly, so batches go through the generic one
+    fn vec_env(&self, _num_envs: usize) -> Option<Box<dyn VecEnv>> { None }
+
+    fn try_step(&mut self, action: usize) -> EnvResult<()> {
+        match &self.step_error {
+            Some(message) => Err(EnvError::failed("step", message.clone())),
+            None => self.env.try_step(action),
+        }
+    }
+}
+
+// Checks that the env obeys the Env contract, by playing num_episodes random episodes from reset.
+// Every visited state must have one mask per action and an observation within obs_shape, states must
+// round-trip through get_state/set_state, clones must not share state, and every episode must reach
+// a final state within max_steps. Returns an error describing the first violation.
+pub fn check_env(env: &dyn Env, num_episodes: usize, max_steps: usize, seed: u64) -> Result<()> {
+    let mut rng = seeded_rng(seed);
+    let mut env = dyn_clone::clone_box(env);
+    for episode in 0..num_episodes {
+        env.try_set_seed(derive_seed(seed, episode as u64))?;
+        env.try_reset()?;
+
+        let mut steps = 0;
+        loop {
+            check_state(env.as_ref())?;
+            if env.try_is_final()? {
+                break;
+            }
+            if steps == max_steps {
+                bail!("episode {} did not reach a final state within {} steps", episode, max_steps);
+            }
+
+            let allowed: Vec<usize> = env.try_masks()?.iter().enumerate().filter(|(_, &m)| m).map(|(a, _)| a).collect();
+            if allowed.is_empty() {
+                bail!("no action is allowed in the non-final state {:?}", env.try_get_state()?);
+            }
+            env.try_step(allowed[rng.gen_range(0..allowed.len())])?;
+            steps += 1;
+        }
+    }
+    Ok(())
+}
+
+// Runs the checks of check_env that only involve the current state
+fn check_state(env: &dyn Env) -> Result<()> {
+    let state = env.try_get_state()?;
+    let observation = env.try_observation()?;
+
+    let num_masks = env.try_masks()?.len();
+    if num_masks != env.num_actions() {
+        bail!("masks() has {} entries but num_actions() is {}, in state {:?}", num_masks, env.num_actions(), state);
+    }
+
+    let obs_size: usize = env.obs_shape().iter().product();
+    let out_of_bounds = match &observation {
+        Observation::Dense(values) => (values.len() != obs_size).then_some(values.len()),
+        _ => observation.to_weighted().into_iter().map(|(i, _)| i).find(|&i| i >= obs_size),
+    };
+    if let Some(index) = out_of_bounds {
+        bail!("observation index {} is out of bounds for obs_shape {:?}, in state {:?}", index, env.obs_shape(), state);
+    }
+
+    // Moving a clone elsewhere must leave the env untouched, and set_state must bring the clone back
+    let mut clone = dyn_clone::clone_box(env);
+    clone.try_reset()?;
+    if let Some(action) = clone.try_masks()?.iter().position(|&m| m) {
+        clone.try_step(action)?;
+    }
+    if env.try_get_state()? != state || env.try_observation()? != observation {
+        bail!("stepping a clone changed the state of the original env {:?}", state);
+    }
+    clone.try_set_state(state.clone())?;
+    if clone.try_get_state()? != state {
+        bail!("set_state({:?}) then get_state() returned {:?}", state, clone.try_get_state()?);
+    }
+    if clone.try_observation()? != observation {
+        bail!("set_state({:?}) then observe() does not give back the observation {:?}", state, observation);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::Puzzle;
+
+    #[test]
+    fn test_check_env() {
+        check_env(&DummyEnv::new(), 3, 10, 0).unwrap();
+        check_env(&Puzzle::new(3, 3, 6, 2, 20), 5, 100, 0).unwrap();
+
+        // Declares only the first dimension of the observation shape
+        let mut broken = TestEnv::new(Box::new(Puzzle::new(3, 3, 6, 2, 20)));
+        broken.obs_shape = Some(vec![9]);
+        let err = check_env(&broken, 5, 100, 0).unwrap_err();
+        assert!(err.to_string().contains("out of bounds"));
+    }
+}
@@ -16,9 +16,12 @@ use rayon::ThreadPoolBuilder;
 use anyhow::Result;
 
 use crate::rl::env::Env;
+use crate::rl::rng::episode_seeds;
 use crate::nn::policy::Policy;
 use super::solve::solve; 
 
+// Same parameters as solve, flattened for the Python bindings, which also pass the env boxed
+#[allow(non_snake_case, clippy::borrowed_box, clippy::too_many_arguments)]
 pub fn evaluate(
     env: &Box<dyn Env>,
     policy: &Policy,
@@ -26,64 +29,51 @@ pub fn evaluate(
     deterministic: bool,
     num_searches: usize,
     num_mcts_searches: usize,
-    _seed: usize,          // unused for now
+    seed: u64,
     C: f32,
     max_expand_depth: usize,
     num_cores: usize,
 ) -> Result<(f32, f32)> {
-    let mut env = env.clone();
-    if num_cores <= 1 {
-        let mut successes = 0.0;
-        let mut rewards  = 0.0;
-        for _ in 0..num_episodes {
-            env.reset();
-            let ((success, reward), _path) = solve(
-                &env,
-                &policy,
-                deterministic,
-                num_searches,
-                num_mcts_searches,
-                C,
-                max_expand_depth,
-            );
-            successes += success;
-            rewards  += reward;
-        }
-        Ok((successes/(num_episodes as f32), rewards/(num_episodes as f32)))
+    // Each episode is fully determined by the seed and its index, so results do not depend on num_cores
+    let run_episode = |episode: usize| -> Result<(f32, f32)> {
+        let (env_seed, solve_seed) = episode_seeds(seed, episode);
+        let mut env = env.clone();
+        env.try_set_seed(env_seed)?;
+        env.try_reset()?;
+        let ((success, reward), _path) = solve(
+            &env,
+            policy,
+            deterministic,
+            num_searches,
+            num_mcts_searches,
+            C,
+            max_expand_depth,
+            solve_seed,
+        )?;
+        Ok((success, reward))
+    };
+
+    let results: Vec<(f32, f32)> = if num_cores <= 1 {
+        (0..num_episodes).map(run_episode).collect::<Result<_>>()?
     } else {
         // Parallel evaluation via Rayon
         let pool = ThreadPoolBuilder::new()
             .num_threads(num_cores)
-            .build()
-            .unwrap();
+            .build()?;
 
-        let (successes, total_vals) = pool.install(|| {
+        pool.install(|| {
             (0..num_episodes)
                 .into_par_iter()
-                .map(|_| {
-                    let mut env = env.clone();
-                    env.reset();
-                    let ((success, reward), _path) = solve(
-                        &env,
-                        &policy,
-                        deterministic,
-                        num_searches,
-                        num_mcts_searches,
-                        C,
-                        max_expand_depth,
-                    );
-                    (success, reward)
-                })
-                .reduce(
-                    || (0.0, 0.0),  // Initialize total successes and rewards to to zero
-                    |mut acc, (success, total_val)| {         
-                        acc.0 += success; // add the successes
-                        acc.1 += total_val;  // add the rewards
-                        acc // return the values
-                    }
-                )
-        });
+                .map(run_episode)
+                .collect::<Result<_>>()
+        })?
+    };
+
+    // Accumulate in episode order so that the sums are the same for any number of cores
+    let (successes, rewards) = results.iter().fold(
+        (0.0, 0.0),  // Initialize total successes and rewards to to zero
+        |acc, &(success, reward)| (acc.0 + success, acc.1 + reward)
+    );
 
-        Ok((successes / (num_episodes as f32), total_vals / (num_episodes as f32)))
-    }
+    Ok((successes / (num_episodes as f32), rewards / (num_episodes as f32)))
 }
\ No newline at end of file
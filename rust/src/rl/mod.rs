@@ -15,4 +15,7 @@ pub mod search;
 pub mod evaluate;
 pub mod tree;
 pub mod env;
-pub mod solve;
\ No newline at end of file
+pub mod solve;
+pub mod rng;
+pub mod vec_env;
+pub mod twists;
@@ -0,0 +1,49 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Derives an independent seed for the given stream (e.g. an episode index) from a master seed.
+// Based on splitmix64, so nearby master seeds and streams give unrelated results.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed
+        .wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Returns a random number generator fully determined by the seed
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+// Seeds used by a single episode: one for the env (reset) and one for the agent (action sampling).
+// They only depend on the master seed and the episode index, never on the thread running the episode.
+pub fn episode_seeds(seed: u64, episode: usize) -> (u64, u64) {
+    let episode_seed = derive_seed(seed, episode as u64);
+    (derive_seed(episode_seed, 0), derive_seed(episode_seed, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_seed_streams_differ() {
+        assert_eq!(derive_seed(7, 3), derive_seed(7, 3));
+        assert_ne!(derive_seed(7, 3), derive_seed(7, 4));
+        assert_ne!(derive_seed(7, 3), derive_seed(8, 3));
+    }
+}
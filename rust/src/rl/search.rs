@@ -11,15 +11,27 @@ copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
 
+// `C` is the exploration constant of PUCT, named as in the AlphaZero paper
+#![allow(non_snake_case)]
 
-use crate::rl::env::Env;
+use rand::Rng;
+use anyhow::Result;
+
+use crate::rl::env::{Env, EnvResult};
 
 use crate::rl::tree::Tree;
 use crate::nn::policy::{sample, Policy};
 
 pub struct MCTSNode {
-    pub state: Box<dyn Env>,
+    // State of the node, None for chance nodes and when the search walks a single env with `undo` instead
+    pub state: Option<Box<dyn Env>>,
     pub action_taken: Option<usize>,
+    // Chance nodes stand for an action of a stochastic env. Its outcomes are sampled on every visit
+    // and stored as children, identified by the state hash in `outcome`.
+    pub chance: bool,
+    pub outcome: Option<u64>,
+    // Player who took `action_taken` (the player to move, for the root). Values are from their point of view.
+    pub player: usize,
     pub prior: f32,
     pub visit_count: u32,
     pub value_sum: f32,
@@ -42,29 +54,42 @@ impl MCTSNode {
 pub type MCTSTree = Tree<MCTSNode>;
 
 impl MCTSTree {
-    pub fn backpropagate(&mut self, node_idx: usize, value: f32) {
+    // Adds a value from the point of view of `player` to the node and its ancestors.
+    // Nodes of the other players get the negated value (two-player zero-sum games).
+    pub fn backpropagate(&mut self, node_idx: usize, value: f32, player: usize) {
         let node = &mut self.nodes[node_idx].val;
-        node.value_sum += value;
+        node.value_sum += if node.player == player { value } else { -value };
         node.visit_count += 1;
 
         if let Some(parent) = self.nodes[node_idx].parent {
-            self.backpropagate(parent, value);
+            self.backpropagate(parent, value, player);
         }
     }
 
-    // Expand the node by adding child nodes based on the policy probabilities
-    pub fn expand(&mut self, node_idx: usize, action_priors: Vec<f32>) {
+    // Expand the node by adding child nodes based on the policy probabilities.
+    // `player` is the player to move in the node, who takes the actions leading to the children.
+    pub fn expand(&mut self, node_idx: usize, action_priors: Vec<f32>, player: usize) -> EnvResult<()> {
         
         for (action, &prob) in action_priors.iter().enumerate() {
             if prob <= 0.0 { continue; }
             
-            let mut next_state = self.nodes[node_idx].val.state.clone();
-            next_state.step(action);
+            let (next_state, chance) = match &self.nodes[node_idx].val.state {
+                Some(state) if state.try_is_stochastic()? => (None, true),
+                Some(state) => {
+                    let mut next_state = state.clone();
+                    next_state.try_step(action)?;
+                    (Some(next_state), false)
+                },
+                None => (None, false),
+            };
 
             self.add_child_to_node(
             MCTSNode{
                     state: next_state,
                     action_taken: Some(action),
+                    chance,
+                    outcome: None,
+                    player,
                     prior: prob,
                     visit_count: 0,
                     value_sum: 0.0
@@ -72,6 +97,39 @@ impl MCTSTree {
                 node_idx
             );
         }
+        Ok(())
+    }
+
+    // Returns the node itself, or for chance nodes a freshly sampled outcome (added as a child if it is new)
+    pub fn resolve_chance(&mut self, node_idx: usize, rng: &mut impl Rng) -> EnvResult<usize> {
+        if !self.nodes[node_idx].val.chance {
+            return Ok(node_idx);
+        }
+        let parent = self.nodes[node_idx].parent.expect("chance nodes must have a parent");
+        let action = self.nodes[node_idx].val.action_taken.expect("chance nodes must have an action");
+        let mut state = self.nodes[parent].val.state.clone().expect("chance nodes must have a parent with a state");
+        state.try_set_seed(rng.gen())?;
+        state.try_step(action)?;
+        let outcome = state.try_state_hash()?;
+
+        if let Some(&child_idx) = self.nodes[node_idx].children.iter()
+            .find(|&&child_idx| self.nodes[child_idx].val.outcome == Some(outcome)) {
+            return Ok(child_idx);
+        }
+        let player = self.nodes[node_idx].val.player;
+        Ok(self.add_child_to_node(
+            MCTSNode {
+                state: Some(state),
+                action_taken: Some(action),
+                chance: false,
+                outcome: Some(outcome),
+                player,
+                prior: 1.0,
+                visit_count: 0,
+                value_sum: 0.0
+            },
+            node_idx
+        ))
     }
 
     pub fn next(&self, node_idx: usize, C: f32) -> usize {
@@ -91,28 +149,55 @@ impl MCTSTree {
     }
 
     // Select the best child node based on UCB score
-    pub fn next_sample(&self, node_idx: usize) -> usize {
+    pub fn next_sample(&self, node_idx: usize, rng: &mut impl Rng) -> usize {
         let probs: Vec<f32> = self.nodes[node_idx].children.iter().map(
             |&child_idx| self.nodes[child_idx].val.prior
         ).collect();
-        let child_num = sample(&probs);
+        let child_num = sample(&probs, rng);
         self.nodes[node_idx].children[child_num]
     }
 }
 
 
+// Returns the env in the state of the given node: its own state, or the walker env when searching with `undo`
+fn node_env<'a>(tree: &'a MCTSTree, node_idx: usize, walker: &'a Option<&mut Box<dyn Env>>) -> &'a dyn Env {
+    match &tree.nodes[node_idx].val.state {
+        Some(state) => &**state,
+        None => &***walker.as_ref().expect("nodes without a state need a walker env"),
+    }
+}
+
+// Steps the walker env (if any) into the given node
+fn walk(walker: &mut Option<&mut Box<dyn Env>>, path: &mut Vec<usize>, tree: &MCTSTree, node_idx: usize) -> EnvResult<()> {
+    if let Some(walker) = walker.as_mut() {
+        let action = tree.nodes[node_idx].val.action_taken.expect("expanded nodes must have an action");
+        walker.try_step(action)?;
+        path.push(action);
+    }
+    Ok(())
+}
+
+// Runs the search from the state of `env`. Deterministic envs that support `undo` are walked up and down the tree
+// (and left in their initial state), the rest are cloned into every node.
 pub fn predict_probs_mcts(
-    env: Box<dyn Env>,
+    env: &mut Box<dyn Env>,
     policy: &Policy,
     num_mcts_searches: usize,
     C: f32,
     max_expand_depth: usize,
-) -> Vec<f32> {
-    // Perform the MCTS search starting from the given state
-    let root_state = env.clone();
-
+    rng: &mut impl Rng,
+) -> Result<Vec<f32>> {
     // Get the initial policy and value from the neural network
-    let (action_probs, _) = policy.full_predict(root_state.observe(), root_state.masks());
+    let (action_probs, _) = policy.full_predict_env(env.as_ref())?;
+    let n_actions = action_probs.len();
+    let root_player = env.try_current_player()?;
+
+    // Perform the MCTS search starting from the given state
+    let (root_state, mut walker) = if env.supports_undo() && !env.try_is_stochastic()? {
+        (None, Some(env))
+    } else {
+        (Some(env.clone()), None)
+    };
 
     // Create the tree and root node
     let mut tree: Tree<MCTSNode> = Tree::new();
@@ -120,50 +205,64 @@ pub fn predict_probs_mcts(
     let root_idx = tree.new_node(MCTSNode {
         state: root_state,
         action_taken: None,
+        chance: false,
+        outcome: None,
+        player: root_player,
         prior: 0.0,
         visit_count: 1,
         value_sum: 0.0,
     });
 
     // Expand the root node
-    tree.expand(root_idx, action_probs);
+    tree.expand(root_idx, action_probs, root_player)?;
 
     // Perform the search iterations
     for _ in 0..num_mcts_searches {
-        let mut node_idx = root_idx.clone();
+        let mut node_idx = root_idx;
+        let mut path = vec![]; // actions applied to the walker env
         
         // First expand until leaf node
         while !tree.nodes[node_idx].children.is_empty() {
             node_idx = tree.next(node_idx, C);
+            node_idx = tree.resolve_chance(node_idx, rng)?;
+            walk(&mut walker, &mut path, &tree, node_idx)?;
         }
         let mut value = 0.0f32;
+        let mut value_player = tree.nodes[node_idx].val.player; // point of view of `value`
         let mut expanded_depth = 0;  // This counts how many steps we expand at most
         
         // Then add nodes until an end state
         while expanded_depth < max_expand_depth {
             // Get value
-            let node_state = &*tree.nodes[node_idx].val.state;
-            value = node_state.reward();
+            let node_state = node_env(&tree, node_idx, &walker);
+            value = node_state.try_reward()?;
+            value_player = node_state.try_current_player()?;
 
             // Break if is_final
-            if node_state.is_final() {
+            if node_state.try_is_final()? {
                 break;
             }
 
             // If not, predict actions, expand tree and select by sampling
-            let (action_probs, new_value) = 
-                policy.full_predict(node_state.observe(), node_state.masks());
-            tree.expand(node_idx, action_probs);
-            node_idx = tree.next_sample(node_idx);
+            let (action_probs, new_value) = policy.full_predict_env(node_state)?;
+            tree.expand(node_idx, action_probs, value_player)?;
+            node_idx = tree.next_sample(node_idx, rng);
+            node_idx = tree.resolve_chance(node_idx, rng)?;
+            walk(&mut walker, &mut path, &tree, node_idx)?;
             value = new_value;
             expanded_depth += 1;
         }
 
         // Backpropagation
-        tree.backpropagate(node_idx, value);
-    }
+        tree.backpropagate(node_idx, value, value_player);
 
-    let n_actions = env.num_actions() as usize;
+        // Walk back to the root
+        if let Some(walker) = walker.as_mut() {
+            for &action in path.iter().rev() {
+                walker.undo(action)?;
+            }
+        }
+    }
 
     // Calculate the action probabilities from the root's children
     let mut mcts_action_probs = vec![0.0f32; n_actions];
@@ -172,7 +271,7 @@ pub fn predict_probs_mcts(
         let act = tree.nodes[child_idx]
             .val
             .action_taken
-            .expect("expanded nodes must have an action") as usize;
+            .expect("expanded nodes must have an action");
         mcts_action_probs[act] = tree.nodes[child_idx].val.visit_count as f32;
     }
 
@@ -185,5 +284,129 @@ pub fn predict_probs_mcts(
         mcts_action_probs = vec![1.0/(n_actions as f32); n_actions];
     }
 
-    mcts_action_probs
+    Ok(mcts_action_probs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::Puzzle;
+    use crate::nn::layers::{EmbeddingBag, Linear};
+    use crate::nn::modules::Sequential;
+    use crate::rl::env::testing::TestEnv;
+    use crate::rl::rng::seeded_rng;
+    use rand::rngs::StdRng;
+
+    // Nim with a single heap: players take 1 or 2 stones, and the player who takes the last stone wins
+    #[derive(Clone)]
+    struct Nim { stones: usize, player: usize }
+
+    impl Env for Nim {
+        fn as_any(&self) -> &dyn std::any::Any { self }
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
+        fn num_actions(&self) -> usize { 2 }
+        fn obs_shape(&self) -> Vec<usize> { vec![8] }
+        fn num_players(&self) -> usize { 2 }
+        fn current_player(&self) -> usize { self.player }
+        fn set_state(&mut self, state: Vec<i64>) { self.stones = state[0] as usize; self.player = state[1] as usize; }
+        fn get_state(&self) -> Vec<i64> { vec![self.stones as i64, self.player as i64] }
+        fn reset(&mut self) { self.stones = 7; self.player = 0; }
+        fn step(&mut self, action: usize) { self.stones -= action + 1; self.player = 1 - self.player; }
+        fn masks(&self) -> Vec<bool> { vec![self.stones >= 1, self.stones >= 2] }
+        fn is_final(&self) -> bool { self.stones == 0 }
+        fn reward(&self) -> f32 { if self.is_final() { -1.0 } else { 0.0 } }
+        fn observe(&self) -> Vec<usize> { vec![self.stones] }
+    }
+
+    // Action 0 wins or loses with equal probability, action 1 gives a small sure reward
+    #[derive(Clone)]
+    struct Gamble { outcome: usize, rng: StdRng }
+
+    impl Env for Gamble {
+        fn as_any(&self) -> &dyn std::any::Any { self }
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any { self }
+        fn num_actions(&self) -> usize { 2 }
+        fn obs_shape(&self) -> Vec<usize> { vec![4] }
+        fn set_seed(&mut self, seed: u64) { self.rng = seeded_rng(seed); }
+        fn set_state(&mut self, state: Vec<i64>) { self.outcome = state[0] as usize; }
+        fn get_state(&self) -> Vec<i64> { vec![self.outcome as i64] }
+        fn reset(&mut self) { self.outcome = 0; }
+        fn step(&mut self, action: usize) {
+            self.outcome = if action == 1 { 3 } else if self.rng.gen_bool(0.5) { 1 } else { 2 };
+        }
+        fn is_stochastic(&self) -> bool { true }
+        fn is_final(&self) -> bool { self.outcome != 0 }
+        fn reward(&self) -> f32 { [0.0, 1.0, -1.0, 0.3][self.outcome] }
+        fn observe(&self) -> Vec<usize> { vec![self.outcome] }
+    }
+
+    fn puzzle_policy() -> Policy {
+        let emb = EmbeddingBag::new((0..81).map(|i| vec![(i % 5) as f32 * 0.1]).collect(), vec![0.0], false, vec![81], 0);
+        let seq_a = Sequential::new(vec![Box::new(Linear::new(vec![0.5, -1.0, 1.0, 0.2], vec![0.0; 4], false))]);
+        let seq_v = Sequential::new(vec![Box::new(Linear::new(vec![1.0], vec![0.0], false))]);
+        Policy::new(
+            Box::new(emb),
+            Box::new(Sequential::new(vec![])),
+            Box::new(seq_a),
+            Box::new(seq_v),
+            vec![],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn test_mcts_undo_matches_cloning() {
+        let mut puzzle = Puzzle::new(3, 3, 6, 1, 20);
+        puzzle.set_seed(3);
+        puzzle.reset();
+        let policy = puzzle_policy();
+
+        let mut undo_env: Box<dyn Env> = Box::new(puzzle.clone());
+        // Without undo, the search clones the env into every node
+        let mut clone_env = TestEnv::new(Box::new(puzzle.clone()));
+        clone_env.no_undo = true;
+        let mut clone_env: Box<dyn Env> = Box::new(clone_env);
+        let undo_probs = predict_probs_mcts(&mut undo_env, &policy, 50, 1.0, 4, &mut seeded_rng(0)).unwrap();
+        let clone_probs = predict_probs_mcts(&mut clone_env, &policy, 50, 1.0, 4, &mut seeded_rng(0)).unwrap();
+
+        assert_eq!(undo_probs, clone_probs);
+        assert!(undo_env.same_state(&puzzle));
+        assert_eq!(undo_env.as_any().downcast_ref::<Puzzle>().unwrap().depth, puzzle.depth);
+    }
+
+    #[test]
+    fn test_mcts_two_player_negates_values() {
+        // Taking one stone leaves a multiple of 3, which loses for the opponent
+        let mut env: Box<dyn Env> = Box::new(Nim { stones: 4, player: 0 });
+        let emb = EmbeddingBag::new(vec![vec![0.0]; 8], vec![0.0], false, vec![8], 0);
+        let policy = Policy::new(
+            Box::new(emb),
+            Box::new(Sequential::new(vec![])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![0.0, 0.0], vec![0.0; 2], false))])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![0.0], vec![0.0], false))])),
+            vec![],
+            vec![],
+        );
+        let probs = predict_probs_mcts(&mut env, &policy, 200, 1.0, 10, &mut seeded_rng(0)).unwrap();
+        assert!(probs[0] > 0.8, "{:?}", probs);
+    }
+
+    #[test]
+    fn test_mcts_samples_stochastic_outcomes() {
+        // Always getting the same sampled outcome of the gamble would make it look like a sure win or loss
+        let policy = Policy::new(
+            Box::new(EmbeddingBag::new(vec![vec![0.0]; 4], vec![0.0], false, vec![4], 0)),
+            Box::new(Sequential::new(vec![])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![0.0, 0.0], vec![0.0; 2], false))])),
+            Box::new(Sequential::new(vec![Box::new(Linear::new(vec![0.0], vec![0.0], false))])),
+            vec![],
+            vec![],
+        );
+        for seed in 0..4 {
+            let mut env: Box<dyn Env> = Box::new(Gamble { outcome: 0, rng: seeded_rng(seed) });
+            let probs = predict_probs_mcts(&mut env, &policy, 300, 1.0, 2, &mut seeded_rng(seed)).unwrap();
+            assert!(probs[1] > probs[0], "{:?}", probs);
+        }
+    }
+}
+
@@ -10,7 +10,16 @@ Any modifications or derivative works of this code must retain this
 copyright notice, and modified files need to carry a notice indicating
 that they have been altered from the originals.
 */
+
+// `C` is the exploration constant of the search, see search.rs
+#![allow(non_snake_case)]
+
+use rand::Rng;
+use anyhow::Result;
+
 use crate::rl::env::Env;
+use crate::rl::env::strict::debug_validate_action;
+use crate::rl::rng::{derive_seed, seeded_rng};
 use crate::nn::policy::{Policy, sample, argmax};
 use super::search::predict_probs_mcts;
 
@@ -21,47 +30,50 @@ pub fn single_solve(
     num_mcts_searches: usize,
     C: f32,
     max_expand_depth: usize,
-) -> ((f32, f32), Vec<usize>) {
+    rng: &mut impl Rng,
+) -> Result<((f32, f32), Vec<usize>)> {
     let mut total_val = 0.0;
     let mut solution = Vec::new();
 
     // step until final
-    while !env.is_final() {
-        let val = env.reward();
-        let obs = env.observe();
-        let masks = env.masks();
+    while !env.try_is_final()? {
+        let val = env.try_reward()?;
         total_val += val;
         
         // choose probs via either policy or MCTS
         let probs = if num_mcts_searches == 0 {
-            policy.predict(obs, masks).0
+            policy.predict_env(env.as_ref(), rng)?.0
         } else {
-            // this will internally clone/reset the env for search
+            // this will internally clone the env or walk it with undo for search
             predict_probs_mcts(
-                env.clone(), 
+                env, 
                 policy, 
                 num_mcts_searches, 
                 C, 
                 max_expand_depth,
-            )
+                rng,
+            )?
         };
 
         let action = if deterministic {
             argmax(&probs)
         } else {
-            sample(&probs)
+            sample(&probs, rng)
         };
 
-        env.step(action);
+        debug_validate_action(env.as_ref(), action)?;
+        env.try_step(action)?;
         solution.push(action);
     }
 
-    let val = env.reward();
-    total_val += val;
+    total_val += env.try_reward()?;
+    let success = env.try_is_success()?;
 
-    (((val == 1.0) as usize as f32, total_val), solution)
+    Ok(((success as usize as f32, total_val), solution))
 }
 
+// Takes the env boxed, as the Python bindings and evaluate hold it
+#[allow(clippy::borrowed_box, clippy::too_many_arguments)]
 pub fn solve(
     env: &Box<dyn Env>,
     policy: &Policy,
@@ -70,11 +82,13 @@ pub fn solve(
     num_mcts_searches: usize,
     C: f32,
     max_expand_depth: usize,
-) -> ((f32, f32), Vec<usize>) {
+    seed: u64,
+) -> Result<((f32, f32), Vec<usize>)> {
     let mut best: ((f32, f32), Vec<usize>) = ((0.0, f32::NEG_INFINITY), Vec::new());
 
-    for _ in 0..num_searches {
+    for search in 0..num_searches {
         let mut cloned_env = env.clone(); // Clone to avoid changing the original env
+        let mut rng = seeded_rng(derive_seed(seed, search as u64)); // Each search gets its own stream
         let next_val = single_solve(
             &mut cloned_env,
             policy,
@@ -82,12 +96,13 @@ pub fn solve(
             num_mcts_searches,
             C,
             max_expand_depth,
-        );
+            &mut rng,
+        )?;
 
         if next_val.0 > best.0 {
             best = next_val;
         }
     }
 
-    best
+    Ok(best)
 }
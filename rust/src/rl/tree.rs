@@ -16,6 +16,8 @@ pub struct Tree<T> {
 }
 
 pub struct Node<T> {
+    // Not read by the search, kept so that a node knows its own position when debugging
+    #[allow(dead_code)]
     idx: usize,
     pub val: T,
     pub parent: Option<usize>,
@@ -34,6 +36,8 @@ impl<T> Node<T> {
 }
 
 
+// Trees are only built empty, through `new`
+#[allow(clippy::new_without_default)]
 impl<T> Tree<T> {
     pub fn new() -> Self {
         Self {nodes: vec![]}
@@ -0,0 +1,207 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use std::fmt;
+use rand::Rng;
+
+use crate::rl::env::{Env, EnvResult, Observation};
+use crate::rl::rng::seeded_rng;
+
+// Why a twist is not a symmetry of the env. `twist` is the index of the twist in the lists returned by Env::twists.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TwistCounterexample {
+    // The twist is not a pair of permutations of the observation features and of the actions
+    Malformed { twist: usize, message: String },
+    // The twist changes the state the walks start from, so the twin can not start from the same state
+    AsymmetricStart { twist: usize, message: String },
+    // The twin does not match the twisted env after the given actions from the start state
+    Mismatch { twist: usize, actions: Vec<usize>, message: String },
+}
+
+impl fmt::Display for TwistCounterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TwistCounterexample::Malformed { twist, message } => write!(f, "Twist {} is malformed: {}", twist, message),
+            TwistCounterexample::AsymmetricStart { twist, message } => write!(
+                f, "Twist {} changes the start state, check from a state it leaves unchanged (e.g. the goal): {}", twist, message
+            ),
+            TwistCounterexample::Mismatch { twist, actions, message } => {
+                write!(f, "Twist {} is inconsistent after actions {:?}: {}", twist, actions, message)
+            }
+        }
+    }
+}
+
+// Checks the twists of the env, see check_perms
+pub fn check_twists(env: &dyn Env, num_walks: usize, walk_length: usize, seed: u64) -> EnvResult<Option<TwistCounterexample>> {
+    let (obs_perms, act_perms) = env.twists();
+    check_perms(env, &obs_perms, &act_perms, num_walks, walk_length, seed)
+}
+
+// Checks that each (obs_perm, act_perm) pair is a symmetry of the env, as assumed by the policy.
+// The env is random-walked from its current state, which must be left unchanged by every twist
+// (e.g. the solved state, otherwise AsymmetricStart is returned), together with a twin env that takes act_perm[a]
+// whenever the env takes a.
+// The twin must then always observe the permuted observation, with the same masks, rewards and final states.
+// Walks stop at the final states reached along the way. Returns the first counterexample found, if any.
+pub fn check_perms(
+    env: &dyn Env,
+    obs_perms: &[Vec<usize>],
+    act_perms: &[Vec<usize>],
+    num_walks: usize,
+    walk_length: usize,
+    seed: u64,
+) -> EnvResult<Option<TwistCounterexample>> {
+    if obs_perms.len() != act_perms.len() {
+        return Ok(Some(TwistCounterexample::Malformed {
+            twist: obs_perms.len().min(act_perms.len()),
+            message: format!("{} observation permutations but {} action permutations", obs_perms.len(), act_perms.len()),
+        }));
+    }
+
+    let obs_size: usize = env.obs_shape().iter().product();
+    let num_actions = env.num_actions();
+    for (twist, (obs_perm, act_perm)) in obs_perms.iter().zip(act_perms.iter()).enumerate() {
+        let malformed = |message: String| Ok(Some(TwistCounterexample::Malformed { twist, message }));
+        if !is_permutation(obs_perm, obs_size) {
+            return malformed(format!("observation permutation is not a permutation of 0..{}", obs_size));
+        }
+        if !is_permutation(act_perm, num_actions) {
+            return malformed(format!("action permutation is not a permutation of 0..{}", num_actions));
+        }
+    }
+
+    // The twin starts as a copy of the env, which is only a twisted copy if the twist leaves the start state unchanged
+    for (twist, obs_perm) in obs_perms.iter().enumerate() {
+        if let Some(message) = compare_observations(env, env, obs_perm)? {
+            return Ok(Some(TwistCounterexample::AsymmetricStart { twist, message }));
+        }
+    }
+
+    let mut rng = seeded_rng(seed);
+    for _ in 0..num_walks {
+        let mut actions = vec![];
+        let mut walker = dyn_clone::clone_box(env);
+        let mut twins: Vec<Box<dyn Env>> = obs_perms.iter().map(|_| dyn_clone::clone_box(env)).collect();
+
+        loop {
+            for (twist, twin) in twins.iter().enumerate() {
+                if let Some(message) = compare_twin(walker.as_ref(), twin.as_ref(), &obs_perms[twist], &act_perms[twist])? {
+                    return Ok(Some(TwistCounterexample::Mismatch { twist, actions, message }));
+                }
+            }
+            if actions.len() == walk_length || (!actions.is_empty() && walker.try_is_final()?) {
+                break;
+            }
+
+            let allowed: Vec<usize> = walker.try_masks()?.iter().enumerate().filter(|(_, &m)| m).map(|(a, _)| a).collect();
+            if allowed.is_empty() {
+                break;
+            }
+            let action = allowed[rng.gen_range(0..allowed.len())];
+            walker.try_step(action)?;
+            for (twist, twin) in twins.iter_mut().enumerate() {
+                twin.try_step(act_perms[twist][action])?;
+            }
+            actions.push(action);
+        }
+    }
+    Ok(None)
+}
+
+fn is_permutation(perm: &[usize], size: usize) -> bool {
+    let mut sorted = perm.to_vec();
+    sorted.sort();
+    sorted == (0..size).collect::<Vec<usize>>()
+}
+
+// Returns a description of the first difference between the twin and the twisted env, if any
+fn compare_twin(env: &dyn Env, twin: &dyn Env, obs_perm: &[usize], act_perm: &[usize]) -> EnvResult<Option<String>> {
+    if let Some(message) = compare_observations(env, twin, obs_perm)? {
+        return Ok(Some(message));
+    }
+
+    let masks = env.try_masks()?;
+    let twin_masks = twin.try_masks()?;
+    for (action, &allowed) in masks.iter().enumerate() {
+        if twin_masks[act_perm[action]] != allowed {
+            return Ok(Some(format!("action {} is {} but the twin action {} is not", action, if allowed { "allowed" } else { "masked" }, act_perm[action])));
+        }
+    }
+
+    let (reward, twin_reward) = (env.try_reward()?, twin.try_reward()?);
+    if reward != twin_reward {
+        return Ok(Some(format!("reward {} does not match the twin reward {}", reward, twin_reward)));
+    }
+    let (is_final, twin_is_final) = (env.try_is_final()?, twin.try_is_final()?);
+    if is_final != twin_is_final {
+        return Ok(Some(format!("is_final is {} but {} for the twin", is_final, twin_is_final)));
+    }
+    Ok(None)
+}
+
+fn compare_observations(env: &dyn Env, twin: &dyn Env, obs_perm: &[usize]) -> EnvResult<Option<String>> {
+    let expected = sorted_features(&env.try_observation()?.permute(obs_perm));
+    let observed = sorted_features(&twin.try_observation()?);
+    if expected != observed {
+        return Ok(Some(format!("permuted observation {:?} does not match the twin observation {:?}", expected, observed)));
+    }
+    Ok(None)
+}
+
+// Features ordered by index, so that observations can be compared regardless of their encoding order
+fn sorted_features(obs: &Observation) -> Vec<(usize, f32)> {
+    let mut features = obs.to_weighted();
+    features.sort_by_key(|&(i, _)| i);
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::Puzzle;
+
+    // Transposing a square puzzle maps tile (x, y) to (y, x), and swaps left/up and right/down
+    fn transpose_twist(size: usize) -> (Vec<usize>, Vec<usize>) {
+        let n = size * size;
+        let t = |i: usize| (i % size) * size + i / size;
+        let obs_perm = (0..n * n).map(|f| t(f / n) * n + t(f % n)).collect();
+        (obs_perm, vec![1, 0, 3, 2])
+    }
+
+    #[test]
+    fn test_check_perms() {
+        let mut puzzle = Puzzle::new(3, 3, 0, 1, 30);
+        puzzle.use_twists = true;
+        puzzle.set_state((0..9).collect());
+        let (obs_perm, act_perm) = transpose_twist(3);
+        let obs_perms = vec![obs_perm];
+        assert_eq!(check_perms(&puzzle, &obs_perms, &[act_perm], 20, 30, 0).unwrap(), None);
+        assert_eq!(check_twists(&puzzle, 20, 30, 0).unwrap(), None);
+
+        // Transposing the board but keeping the actions is wrong as soon as the blank moves
+        let counterexample = check_perms(&puzzle, &obs_perms, &[vec![0, 1, 2, 3]], 20, 30, 0).unwrap().unwrap();
+        assert!(matches!(counterexample, TwistCounterexample::Mismatch { twist: 0, ref actions, .. } if !actions.is_empty()));
+
+        let counterexample = check_perms(&puzzle, &obs_perms, &[vec![0, 1, 2]], 20, 30, 0).unwrap().unwrap();
+        assert!(matches!(counterexample, TwistCounterexample::Malformed { twist: 0, .. }));
+
+        // A scrambled board is not symmetric, so the walks can not start from it
+        let mut puzzle = Puzzle::new(3, 3, 10, 1, 20);
+        puzzle.use_twists = true;
+        puzzle.set_seed(0);
+        puzzle.reset();
+        let counterexample = check_twists(&puzzle, 20, 30, 0).unwrap().unwrap();
+        assert!(matches!(counterexample, TwistCounterexample::AsymmetricStart { twist: 1, .. }));
+    }
+}
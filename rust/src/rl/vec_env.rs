@@ -0,0 +1,204 @@
+// -*- coding: utf-8 -*-
+/* 
+(C) Copyright 2025 IBM. All Rights Reserved.
+
+This code is licensed under the Apache License, Version 2.0. You may
+obtain a copy of this license in the LICENSE.txt file in the root directory
+of this source tree or at http://www.apache.org/licenses/LICENSE-2.0.
+
+Any modifications or derivative works of this code must retain this
+copyright notice, and modified files need to carry a notice indicating
+that they have been altered from the originals.
+*/
+
+use crate::rl::env::{Env, EnvError, EnvResult, Observation};
+use crate::rl::rng::derive_seed;
+
+// Outcome of stepping all the envs of a VecEnv with one action each
+#[derive(Debug, Clone, PartialEq)]
+pub struct VecStep {
+    // Reward of the state reached by each env (before any reset)
+    pub rewards: Vec<f32>,
+    // True for the envs that reached a final state, and were reset
+    pub dones: Vec<bool>,
+    // True for the envs whose episode was cut short
+    pub truncated: Vec<bool>,
+    // True for the envs that reached a successful state
+    pub successes: Vec<bool>,
+    // Observation of the final state of the envs that were reset
+    pub final_observations: Vec<Option<Observation>>,
+}
+
+impl VecStep {
+    pub fn with_capacity(num_envs: usize) -> Self {
+        VecStep {
+            rewards: Vec::with_capacity(num_envs),
+            dones: Vec::with_capacity(num_envs),
+            truncated: Vec::with_capacity(num_envs),
+            successes: Vec::with_capacity(num_envs),
+            final_observations: Vec::with_capacity(num_envs),
+        }
+    }
+}
+
+// A batch of envs of the same kind that are stepped in lockstep. Envs that reach a final state are reset automatically.
+pub trait VecEnv : Send + Sync {
+    // Returns the number of envs in the batch
+    fn num_envs(&self) -> usize;
+
+    // Returns the number of possible actions of each env
+    fn num_actions(&self) -> usize;
+
+    // Returns the size of the observations of each env
+    fn obs_shape(&self) -> Vec<usize>;
+
+    // Sets the difficulty of all the envs
+    fn set_difficulty(&mut self, difficulty: usize);
+
+    // Seeds the env i with a seed derived from (seed, i)
+    fn set_seed(&mut self, seed: u64) -> EnvResult<()>;
+
+    // Sets all the envs to a random initial state
+    fn reset(&mut self) -> EnvResult<()>;
+
+    // Returns the current observation of each env
+    fn observations(&self) -> EnvResult<Vec<Observation>>;
+
+    // Returns the action masks of each env
+    fn masks(&self) -> EnvResult<Vec<Vec<bool>>>;
+
+    // Steps env i with actions[i], resetting the envs that reach a final state
+    fn step(&mut self, actions: &[usize]) -> EnvResult<VecStep>;
+}
+
+// Returns an error unless there is exactly one action per env
+pub fn check_num_actions(num_envs: usize, actions: &[usize]) -> EnvResult<()> {
+    if actions.len() != num_envs {
+        return Err(EnvError::failed("step", format!("expected one action per env ({}), got {}", num_envs, actions.len())));
+    }
+    Ok(())
+}
+
+// VecEnv over copies of any env
+#[derive(Clone)]
+pub struct DynVecEnv {
+    envs: Vec<Box<dyn Env>>,
+}
+
+impl DynVecEnv {
+    #[allow(clippy::borrowed_box)]
+    pub fn new(env: &Box<dyn Env>, num_envs: usize) -> Self {
+        DynVecEnv { envs: (0..num_envs).map(|_| env.clone()).collect() }
+    }
+}
+
+impl VecEnv for DynVecEnv {
+    fn num_envs(&self) -> usize {
+        self.envs.len()
+    }
+
+    fn num_actions(&self) -> usize {
+        self.envs[0].num_actions()
+    }
+
+    fn obs_shape(&self) -> Vec<usize> {
+        self.envs[0].obs_shape()
+    }
+
+    fn set_difficulty(&mut self, difficulty: usize) {
+        for env in self.envs.iter_mut() {
+            env.set_difficulty(difficulty);
+        }
+    }
+
+    fn set_seed(&mut self, seed: u64) -> EnvResult<()> {
+        for (i, env) in self.envs.iter_mut().enumerate() {
+            env.try_set_seed(derive_seed(seed, i as u64))?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> EnvResult<()> {
+        for env in self.envs.iter_mut() {
+            env.try_reset()?;
+        }
+        Ok(())
+    }
+
+    fn observations(&self) -> EnvResult<Vec<Observation>> {
+        self.envs.iter().map(|env| env.try_observation()).collect()
+    }
+
+    fn masks(&self) -> EnvResult<Vec<Vec<bool>>> {
+        self.envs.iter().map(|env| env.try_masks()).collect()
+    }
+
+    fn step(&mut self, actions: &[usize]) -> EnvResult<VecStep> {
+        check_num_actions(self.envs.len(), actions)?;
+        let mut out = VecStep::with_capacity(self.envs.len());
+        for (env, &action) in self.envs.iter_mut().zip(actions.iter()) {
+            env.try_step(action)?;
+            out.rewards.push(env.try_reward()?);
+            out.successes.push(env.try_is_success()?);
+            let done = env.try_is_final()?;
+            out.dones.push(done);
+            if done {
+                out.truncated.push(env.try_is_truncated()?);
+                out.final_observations.push(Some(env.try_observation()?));
+                env.try_reset()?;
+            } else {
+                out.truncated.push(false);
+                out.final_observations.push(None);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envs::puzzle::{Puzzle, ResetMode};
+
+    #[test]
+    fn test_puzzle_vec_env_matches_dyn_vec_env() {
+        let env: Box<dyn Env> = Box::new(Puzzle::new(3, 3, 4, 1, 4));
+        let mut fast = env.vec_env(3).unwrap();
+        let mut generic: Box<dyn VecEnv> = Box::new(DynVecEnv::new(&env, 3));
+        for vec_env in [&mut fast, &mut generic] {
+            vec_env.set_seed(11).unwrap();
+            vec_env.reset().unwrap();
+        }
+
+        let mut num_dones = 0;
+        for t in 0..12 {
+            assert_eq!(fast.observations().unwrap(), generic.observations().unwrap());
+            let masks = fast.masks().unwrap();
+            assert_eq!(masks, generic.masks().unwrap());
+            // First allowed action after a rotating offset
+            let actions: Vec<usize> = masks.iter()
+                .map(|m| (0..4).map(|a| (a + t) % 4).find(|&a| m[a]).unwrap())
+                .collect();
+            let step = fast.step(&actions).unwrap();
+            assert_eq!(step, generic.step(&actions).unwrap());
+            num_dones += step.dones.iter().filter(|&&done| done).count();
+        }
+        assert!(num_dones >= 3); // every env runs out of depth at least once, and is reset
+        assert!(fast.step(&[0, 0]).is_err());
+        assert!(generic.step(&[0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_vec_env_reset_error() {
+        // The envs run out of depth after one step, and no 2x2 board is 7 moves away from the goal
+        let mut puzzle = Puzzle::new(2, 2, 1, 1, 10);
+        puzzle.reset_mode = ResetMode::ExactDistance;
+        let env: Box<dyn Env> = Box::new(puzzle);
+        for mut vec_env in [env.vec_env(2).unwrap(), Box::new(DynVecEnv::new(&env, 2))] {
+            vec_env.reset().unwrap();
+            vec_env.set_difficulty(7);
+            let actions: Vec<usize> = vec_env.masks().unwrap().iter().map(|m| m.iter().position(|&a| a).unwrap()).collect();
+            assert!(vec_env.step(&actions).is_err());
+        }
+    }
+}
@@ -66,6 +66,8 @@ PPO_CONFIG = {
         "num_episodes": 512 * 32,
         "lambda": 0.995,
         "gamma": 0.995,
+        "seed": 0,
+        "difficulty_distribution": {"type": "fixed"},
     },
     # Train params
     "training": {
@@ -88,6 +90,7 @@ AZ_CONFIG = {
         "C": 1.41,
         "max_expand_depth": 1,
         "seed": 123,
+        "difficulty_distribution": {"type": "fixed"},
     },
     # Train params
     "training": {
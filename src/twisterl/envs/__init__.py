@@ -10,11 +10,14 @@
 # copyright notice, and modified files need to carry a notice indicating
 # that they have been altered from the originals.
 
+import json
+
 from twisterl import twisterl
 
 from twisterl.utils import dynamic_import
 
 Puzzle = twisterl.env.Puzzle
+PermutationPuzzle = twisterl.env.PermutationPuzzle
 
 
 class PyEnv(twisterl.env.PyEnv):
@@ -23,3 +26,8 @@ class PyEnv(twisterl.env.PyEnv):
         env_cls = dynamic_import(pyenv_cls)
         instance = super().__new__(cls, env_cls(**env_config))
         return instance
+
+
+def make_env(name, **env_config):
+    # Builds an env from the Rust registry (see twisterl.env.registered_envs())
+    return twisterl.env.make_env(name, json.dumps(env_config))
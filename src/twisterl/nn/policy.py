@@ -28,6 +28,7 @@ class BasicPolicy(torch.nn.Module):
         obs_perms=tuple(),
         act_perms=tuple(),
         device="cuda",
+        action_dims=None,
     ):
         super().__init__()
         self.obs_shape = obs_shape
@@ -42,8 +43,11 @@ class BasicPolicy(torch.nn.Module):
         else:
             self.common = torch.nn.Sequential()
 
+        # With factored actions, the action net outputs the logits of each component
+        self.action_dims = list(action_dims) if action_dims else None
+        num_logits = sum(self.action_dims) if self.action_dims else num_actions
         self.action = make_sequential(
-            in_size, tuple(policy_layers) + (num_actions,), final_relu=False
+            in_size, tuple(policy_layers) + (num_logits,), final_relu=False
         )
         self.value = make_sequential(
             in_size, tuple(value_layers) + (1,), final_relu=False
@@ -73,6 +77,7 @@ class BasicPolicy(torch.nn.Module):
             sequential_to_rust(self.value),
             self.obs_perms,
             self.act_perms,
+            self.action_dims,
         )
 
 
@@ -93,6 +98,7 @@ class Conv1dPolicy(BasicPolicy):
         value_layers=tuple(),
         obs_perms=tuple(),
         act_perms=tuple(),
+        action_dims=None,
     ):
         super().__init__(
             obs_shape,
@@ -103,6 +109,7 @@ class Conv1dPolicy(BasicPolicy):
             value_layers,
             obs_perms,
             act_perms,
+            action_dims=action_dims,
         )
         self.conv_dim = conv_dim
 
@@ -135,4 +142,5 @@ class Conv1dPolicy(BasicPolicy):
             sequential_to_rust(self.value),
             self.obs_perms,
             self.act_perms,
+            self.action_dims,
         )
@@ -16,6 +16,7 @@ from abc import abstractmethod
 from functools import wraps
 from loguru import logger
 
+import numpy as np
 import torch
 from torch.utils.tensorboard import SummaryWriter
 
@@ -27,6 +28,25 @@ evaluate = twisterl.collector.evaluate
 solve = twisterl.collector.solve
 
 
+def obs_to_numpy(obs, obs_size):
+    """Converts sparse, weighted or dense observations into a dense numpy array."""
+    np_obs = np.zeros((len(obs), obs_size), dtype=float)
+    for i, obs_i in enumerate(obs):
+        if len(obs_i) == 0:
+            continue
+        if isinstance(obs_i[0], tuple):
+            # Weighted observation: (index, value) pairs
+            indices, values = zip(*obs_i)
+            np.add.at(np_obs[i], list(indices), values)
+        elif isinstance(obs_i[0], float):
+            # Dense observation
+            np_obs[i, : len(obs_i)] = obs_i
+        else:
+            # Sparse observation: active indices
+            np_obs[i, obs_i] = 1.0
+    return np_obs
+
+
 def timed(func):
     @wraps(func)
     def wrapper(*args, **kwargs):
@@ -230,6 +250,8 @@ class Algorithm:
         num_mcts_searches=0,
         C=(2**0.5),
         max_expand_depth=1,
+        seed=None,
+        named=False,
     ):
         self.env.set_state(state)
 
@@ -242,7 +264,11 @@ class Algorithm:
             num_mcts_searches=num_mcts_searches,
             C=C,
             max_expand_depth=max_expand_depth,
+            seed=seed,
         )
 
         if success:
+            # Action names (e.g. "left") instead of indices, for readable solutions
+            if named:
+                return [self.env.action_name(action) for action in actions]
             return actions
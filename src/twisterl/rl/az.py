@@ -11,9 +11,8 @@
 # that they have been altered from the originals.
 
 import torch
-import numpy as np
 
-from twisterl.rl.algorithm import Algorithm, timed
+from twisterl.rl.algorithm import Algorithm, obs_to_numpy, timed
 from twisterl import twisterl
 
 
@@ -30,9 +29,7 @@ class AZ(Algorithm):
             data.additional_data["remaining_values"],
         )
 
-        np_obs = np.zeros((len(obs), self.obs_size), dtype=float)
-        for i, obs_i in enumerate(obs):
-            np_obs[i, obs_i] = 1.0
+        np_obs = obs_to_numpy(obs, self.obs_size)
 
         pt_obs = torch.tensor(np_obs, dtype=torch.float, device=self.config["device"])
         pt_probs = torch.tensor(probs, dtype=torch.float, device=self.config["device"])
@@ -11,12 +11,32 @@
 # that they have been altered from the originals.
 
 import torch
-import numpy as np
 
-from twisterl.rl.algorithm import Algorithm, timed
+from twisterl.rl.algorithm import Algorithm, obs_to_numpy, timed
 from twisterl import twisterl
 
 
+def action_log_probs(logits, acts, action_dims=None):
+    """Log-probabilities and entropies of the actions under the logits.
+
+    For factored action spaces the logits are the concatenated heads of the action
+    components, and the flat actions are decoded into their components.
+    """
+    if not action_dims:
+        dist = torch.distributions.Categorical(logits=logits)
+        return dist.log_prob(acts), dist.entropy()
+
+    log_probs, entropy = 0.0, 0.0
+    heads = torch.split(logits, list(action_dims), dim=-1)
+    # The first component is the most significant
+    for head, dim in reversed(list(zip(heads, action_dims))):
+        dist = torch.distributions.Categorical(logits=head)
+        log_probs = log_probs + dist.log_prob(acts % dim)
+        entropy = entropy + dist.entropy()
+        acts = torch.div(acts, dim, rounding_mode="floor")
+    return log_probs, entropy
+
+
 class PPO(Algorithm):
     def __init__(self, env, policy, config, run_path=None):
         super().__init__(env, policy, config, run_path)
@@ -33,9 +53,7 @@ class PPO(Algorithm):
             data.additional_data["rets"],
             data.additional_data["advs"],
         )
-        np_obs = np.zeros((len(obs), self.obs_size), dtype=float)
-        for i, obs_i in enumerate(obs):
-            np_obs[i, obs_i] = 1.0
+        np_obs = obs_to_numpy(obs, self.obs_size)
 
         pt_obs = torch.tensor(np_obs, dtype=torch.float, device=self.config["device"])
         pt_logits = torch.tensor(
@@ -50,8 +68,8 @@ class PPO(Algorithm):
         with torch.no_grad():
             if self.config["training"].get("normalize_advantage", False):
                 pt_advs = (pt_advs - pt_advs.mean()) / (pt_advs.std() + 1e-8)
-            pt_log_probs = torch.distributions.Categorical(logits=pt_logits).log_prob(
-                pt_acts
+            pt_log_probs, _ = action_log_probs(
+                pt_logits, pt_acts, getattr(self.policy, "action_dims", None)
             )
 
         return pt_obs, pt_log_probs, pt_acts, pt_advs, pt_rets
@@ -63,12 +81,10 @@ class PPO(Algorithm):
         # Forward pass to get logits and values
         pred_logits, pred_vals = self.policy(pt_obs)
 
-        # Get log-probabilities of the actions actually taken
-        dist = torch.distributions.Categorical(logits=pred_logits)
-        pred_log_probs = dist.log_prob(pt_acts)
-
-        # Entropy of the action distribution
-        entropy = dist.entropy()
+        # Log-probabilities of the actions taken and entropy of the action distribution
+        pred_log_probs, entropy = action_log_probs(
+            pred_logits, pt_acts, getattr(self.policy, "action_dims", None)
+        )
         entropy_loss = entropy.mean()
 
         # Compute the ratio between new and old policy probabilities
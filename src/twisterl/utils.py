@@ -38,12 +38,21 @@ def prepare_algorithm(config, run_path=None, load_checkpoint_path=None):
     # Import policy class and make policy
     policy_cls = dynamic_import(config["policy_cls"])
     obs_perms, act_perms = env.twists()
+    # Factored action spaces get one head of logits per action component
+    action_dims = env.action_dims() if hasattr(env, "action_dims") else None
+    factored = (
+        {"action_dims": action_dims} if action_dims and len(action_dims) > 1 else {}
+    )
+    # Twists permute the flat actions, not the heads of a factored policy
+    if factored and act_perms:
+        raise ValueError("Twists are not supported for factored action spaces")
     policy = policy_cls(
         env.obs_shape(),
         env.num_actions(),
         **config["policy"],
         obs_perms=obs_perms,
         act_perms=act_perms,
+        **factored,
     )
     if load_checkpoint_path is not None:
         policy.load_state_dict(
@@ -1,7 +1,9 @@
 import json
 import numpy as np
+import pytest
 import torch
 
+from twisterl import twisterl
 from twisterl.utils import load_config, prepare_algorithm
 from twisterl.defaults import make_config
 from twisterl.nn.utils import sequential_to_rust, embeddingbag_to_rust
@@ -28,6 +30,26 @@ class DummyEnv:
     def set_state(self, state):
         self.state = state
 
+    def get_state(self):
+        return self.state
+
+
+class FactoredTwistEnv(DummyEnv):
+    def action_dims(self):
+        return [1, self.size]
+
+    def twists(self):
+        return [list(range(self.size))], [list(reversed(range(self.size)))]
+
+
+class DenseObsEnv(DummyEnv):
+    def __init__(self, obs, size=3):
+        super().__init__(size)
+        self.obs = obs
+
+    def observe(self):
+        return self.obs
+
 
 def test_load_config(tmp_path):
     p = tmp_path / "cfg.json"
@@ -56,6 +78,18 @@ def test_prepare_algorithm():
     assert isinstance(algo.policy, BasicPolicy)
     assert isinstance(algo, PPO)
 
+    config["env_cls"] = f"{__name__}.FactoredTwistEnv"
+    with pytest.raises(ValueError):
+        prepare_algorithm(config)
+
+
+def test_pyenv_observation_kinds():
+    # A list of ints is sparse, dense observations with int values are tagged
+    assert twisterl.env.PyEnv(DenseObsEnv([0, 2])).observation() == [0, 2]
+    dense = twisterl.env.PyEnv(DenseObsEnv(("dense", [0, 1, 0]))).observation()
+    assert dense == [0.0, 1.0, 0.0] and all(isinstance(v, float) for v in dense)
+    assert twisterl.env.PyEnv(DenseObsEnv([0.0, 1.0, 0.0])).observation() == [0.0, 1.0, 0.0]
+
 
 def test_sequential_and_embeddingbag_to_rust():
     seq = torch.nn.Sequential(torch.nn.Linear(3, 2), torch.nn.ReLU(), torch.nn.Linear(2, 1))